@@ -1,106 +1,771 @@
-use crate::executables::find_executable_in_path;
+use crate::completion::{self, CompSpec, CurrentWord};
+use crate::conditional;
+use crate::dirs;
+use crate::executables::{self, find_executable_in_path, find_executable_to_run};
+use crate::external;
+use crate::fields;
+use crate::histdb;
+use crate::histexpand;
 use crate::history;
+use crate::jump;
+use crate::parse::Command;
+use crate::printf;
+use crate::prompt;
+use crate::state;
+use crate::sys;
 
 use std::collections::HashMap;
 use std::env;
-use std::io::{self, Write, Result};
-use std::path::Path;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Read, Result, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::ChildStdout;
 use std::sync::LazyLock;
+use std::time::{Duration, Instant};
 
-type BuiltinFn = fn(args: &[String], stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<()>;
+/// A builtin gets its arguments (without the command name) and returns its exit status.
+type BuiltinFn = fn(args: &[String], stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32>;
+
+/// What a builtin reads: the shell's stdin, the pipe from the previous command, or the buffered output
+/// of a previous builtin.
+pub trait Input: Read + Send {
+    /// Waits at most `timeout_ms` for input to be available (`read -t`); false on timeout.
+    fn wait(&mut self, _timeout_ms: i32) -> bool {
+        true
+    }
+
+    /// Whether this is the shell's stdin and that is a terminal.
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+impl Input for io::Stdin {
+    fn wait(&mut self, timeout_ms: i32) -> bool {
+        // The rest of a script fed to the shell may already sit in the stdin buffer, where polling can't see it:
+        // peek without blocking first. Buffered bytes or the end of input mean `read` won't wait.
+        let fd = self.as_raw_fd();
+        let mut stdin = self.lock();
+        match sys::with_nonblocking(fd, || stdin.fill_buf().map(|buf| buf.len())) {
+            Ok(_) => true,
+            Err(_) => sys::wait_readable(fd, timeout_ms),
+        }
+    }
+
+    fn is_terminal(&self) -> bool {
+        IsTerminal::is_terminal(self)
+    }
+}
+
+/// The terminal on stdin, read byte by byte past `io::Stdin`'s buffer, so that whatever `read` leaves
+/// (typed ahead for the next prompt) stays with the terminal for the line editor.
+struct Terminal;
+
+impl Read for Terminal {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        sys::read_fd(libc::STDIN_FILENO, buf)
+    }
+}
+
+impl Input for Terminal {
+    fn wait(&mut self, timeout_ms: i32) -> bool {
+        sys::wait_readable(libc::STDIN_FILENO, timeout_ms)
+    }
+
+    fn is_terminal(&self) -> bool {
+        true
+    }
+}
+
+impl Input for ChildStdout {
+    fn wait(&mut self, timeout_ms: i32) -> bool {
+        sys::wait_readable(self.as_raw_fd(), timeout_ms)
+    }
+}
+
+impl Input for io::Cursor<Vec<u8>> {}
+
+impl Input for io::Empty {}
 static BUILTINS: LazyLock<HashMap<&'static str, BuiltinFn>> = LazyLock::new(|| {
     let mut m: HashMap<&'static str, BuiltinFn> = HashMap::new();
+    m.insert(CMD_BRACKET, bracket);
     m.insert(CMD_CD, cd);
+    m.insert(CMD_COMMAND, command);
+    m.insert(CMD_COMPGEN, compgen);
+    m.insert(CMD_COMPLETE, complete);
+    m.insert(CMD_DIRS, dirs);
     m.insert(CMD_ECHO, echo);
+    m.insert(CMD_EXPORT, export);
+    m.insert(CMD_HASH, hash);
     m.insert(CMD_HISTORY, history);
+    m.insert(CMD_J, j);
+    m.insert(CMD_POPD, popd);
+    m.insert(CMD_PRINTF, printf);
+    m.insert(CMD_PUSHD, pushd);
     m.insert(CMD_PWD, pwd);
+    m.insert(CMD_READ, read);
+    m.insert(CMD_SET, set);
+    m.insert(CMD_TEST, test);
     m.insert(CMD_TYPE, type_of);
     m
 });
 
+/// Runs builtin `cmd` and returns its exit status; `None` if there is no such builtin.
+/// An `Err` means writing the output failed.
 pub fn run_builtin(
     cmd: &str,
     args: &[String],
+    stdin: &mut dyn Input,
     stdout: &mut dyn Write,
     stderr: &mut dyn Write
-) -> Option<Result<()>> {
+) -> Option<Result<i32>> {
     // Look up the function in our map
     let fun = BUILTINS.get(cmd)?;
-    Some(fun(args, stdout, stderr))
+    Some(fun(args, stdin, stdout, stderr))
 }
 
+pub const CMD_BRACKET: &str = "[";
 pub const CMD_CD: &str = "cd";
+pub const CMD_COMMAND: &str = "command";
+pub const CMD_COMPGEN: &str = "compgen";
+pub const CMD_COMPLETE: &str = "complete";
+pub const CMD_DIRS: &str = "dirs";
 pub const CMD_ECHO: &str = "echo";
 pub const CMD_EXIT: &str = "exit";
+pub const CMD_EXPORT: &str = "export";
+pub const CMD_HASH: &str = "hash";
 pub const CMD_HISTORY: &str = "history";
+pub const CMD_J: &str = "j";
+pub const CMD_POPD: &str = "popd";
+pub const CMD_PRINTF: &str = "printf";
+pub const CMD_PUSHD: &str = "pushd";
 pub const CMD_PWD: &str = "pwd";
+pub const CMD_READ: &str = "read";
+pub const CMD_SET: &str = "set";
+pub const CMD_TEST: &str = "test";
 pub const CMD_TYPE: &str = "type";
 
 pub fn all() -> Vec<&'static str> {
-    vec![CMD_CD, CMD_ECHO, CMD_EXIT, CMD_HISTORY, CMD_PWD, CMD_TYPE]
+    vec![
+        CMD_BRACKET, CMD_CD, CMD_COMMAND, CMD_COMPGEN, CMD_COMPLETE, CMD_DIRS, CMD_ECHO, CMD_EXIT, CMD_EXPORT,
+        CMD_HASH, CMD_HISTORY, CMD_J, CMD_POPD, CMD_PRINTF, CMD_PUSHD, CMD_PWD, CMD_READ, CMD_SET,
+        CMD_TEST, CMD_TYPE,
+    ]
 }
 
-pub fn type_of(args: &[String], stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<()> {
-    if args.is_empty() {
-        return Ok(());
+/// Prints `message` as an error and returns the failure status 1.
+fn fail(stderr: &mut dyn Write, message: fmt::Arguments) -> Result<i32> {
+    writeln!(stderr, "{message}")?;
+    Ok(1)
+}
+
+/// The reserved words of the shell grammar.
+const KEYWORDS: &[&str] = &["[[", "]]"];
+
+/// What a command name runs. The shell has no aliases or functions, so these are the only kinds.
+enum Kind {
+    Keyword,
+    Builtin,
+    File(PathBuf),
+}
+
+/// Everything `name` could run, in the order the shell tries it: the builtin, then every match in `PATH`
+/// (with `all`), or just the first one. `path_only` skips the builtin (`type -P`).
+fn lookup(name: &str, all_matches: bool, path_only: bool) -> Vec<Kind> {
+    let mut kinds = Vec::new();
+    if !path_only && KEYWORDS.contains(&name) {
+        kinds.push(Kind::Keyword);
+        if !all_matches {
+            return kinds;
+        }
+    }
+    if !path_only && all().contains(&name) {
+        kinds.push(Kind::Builtin);
+        if !all_matches {
+            return kinds;
+        }
+    }
+    if all_matches {
+        kinds.extend(executables::find_all_in_path(name).into_iter().map(Kind::File));
+    } else if let Some(path) = find_executable_in_path(name) {
+        kinds.push(Kind::File(path));
+    }
+    kinds
+}
+
+/// `type [-afptP] name...` says what each name runs. `-t` prints only the kind (`keyword`, `builtin` or `file`),
+/// `-p` only the path of a file (nothing for a keyword or builtin), `-P` the path even if a builtin of that name exists,
+/// `-a` every match instead of the first. Fails if any name is not found.
+pub fn type_of(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let (mut all_matches, mut kind_only, mut path, mut force_path) = (false, false, false, false);
+    let mut names = args.iter();
+    while let Some(arg) = names.as_slice().first() {
+        if arg == "--" {
+            names.next();
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            break;
+        }
+        for flag in arg[1..].chars() {
+            match flag {
+                'a' => all_matches = true,
+                'f' => {} // no functions to skip
+                't' => kind_only = true,
+                'p' => path = true,
+                'P' => force_path = true,
+                _ => return fail(stderr, format_args!("type: -{flag}: invalid option")),
+            }
+        }
+        names.next();
+    }
+
+    let mut status = 0;
+    for name in names {
+        let kinds = lookup(name, all_matches, force_path);
+        if kinds.is_empty() {
+            if !kind_only && !path && !force_path {
+                writeln!(stderr, "{name}: not found")?;
+            }
+            status = 1;
+        }
+        for kind in kinds {
+            match kind {
+                Kind::Keyword if kind_only => writeln!(stdout, "keyword")?,
+                Kind::Keyword if path => {}
+                Kind::Keyword => writeln!(stdout, "{name} is a shell keyword")?,
+                Kind::Builtin if kind_only => writeln!(stdout, "builtin")?,
+                Kind::Builtin if path => {}
+                Kind::Builtin => writeln!(stdout, "{name} is a shell builtin")?,
+                Kind::File(_) if kind_only => writeln!(stdout, "file")?,
+                Kind::File(p) if path || force_path => writeln!(stdout, "{}", p.display())?,
+                Kind::File(p) => writeln!(stdout, "{name} is {}", p.display())?,
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `command -v name...` prints how each name would be run (the name of a builtin, the path of a file),
+/// `command -V name...` describes it like `type`; both fail if a name is not found.
+/// `command name args...` runs `name` skipping any alias or function; the executor unwraps it
+/// (see `bypass`), so this only runs when `command` itself is run from a pipeline thread or completion.
+pub fn command(args: &[String], stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let (mut verbose, mut short) = (false, false);
+    let mut rest = args;
+    while let Some(arg) = rest.first() {
+        match arg.as_str() {
+            "--" => {
+                rest = &rest[1..];
+                break;
+            }
+            "-p" => {} // `PATH` is always searched
+            "-v" => short = true,
+            "-V" => verbose = true,
+            opt if opt.starts_with('-') && opt.len() > 1 => {
+                return fail(stderr, format_args!("command: {opt}: invalid option"));
+            }
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+
+    if verbose {
+        return type_of(rest, stdin, stdout, stderr);
+    }
+    if short {
+        let mut status = 0;
+        for name in rest {
+            match lookup(name, false, false).first() {
+                Some(Kind::Keyword | Kind::Builtin) => writeln!(stdout, "{name}")?,
+                Some(Kind::File(p)) => writeln!(stdout, "{}", p.display())?,
+                None => status = 1,
+            }
+        }
+        return Ok(status);
+    }
+
+    let Some((name, args)) = rest.split_first() else {
+        return Ok(0);
+    };
+    if let Some(result) = run_builtin(name, args, stdin, stdout, stderr) {
+        return result;
+    }
+    match find_executable_to_run(name) {
+        Some(path) => Ok(external::run_into(&path, name, args, stdout, stderr).unwrap_or(126)),
+        None => {
+            writeln!(stderr, "{name}: command not found")?;
+            Ok(127)
+        }
+    }
+}
+
+/// `command [-p] [--] name args...` runs `name` itself: with no aliases or functions to skip, that is the
+/// command without the `command` prefix. Other uses of `command` are left to the builtin.
+pub fn bypass(command: Command) -> Command {
+    match command {
+        Command::SimpleCommand(cmd, mut args) if cmd == CMD_COMMAND => {
+            let mut start = args.iter().take_while(|a| *a == "-p").count();
+            if args.get(start).is_some_and(|a| a == "--") {
+                start += 1;
+            }
+            match args.get(start) {
+                Some(name) if !name.starts_with('-') => {
+                    let name = name.clone();
+                    Command::SimpleCommand(name, args.split_off(start + 1))
+                }
+                _ => Command::SimpleCommand(cmd, args),
+            }
+        }
+        Command::PipeCommand(commands) => Command::PipeCommand(commands.into_iter().map(bypass).collect()),
+        Command::RedirectCommand(inner, path, kind) => Command::RedirectCommand(Box::new(bypass(*inner)), path, kind),
+        other => other,
+    }
+}
+
+/// `echo [-neE] args...` prints the arguments separated by spaces. `-n` leaves out the newline,
+/// `-e` expands backslash escapes (`\c` stops the output there), `-E` turns that off again.
+pub fn echo(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, _stderr: &mut dyn Write) -> Result<i32> {
+    let (mut newline, mut escapes) = (true, false);
+    let mut words = args;
+    // like bash, only words made of option letters are options; anything else is printed
+    while let Some(opt) = words.first().and_then(|w| w.strip_prefix('-'))
+        && !opt.is_empty()
+        && opt.chars().all(|c| "neE".contains(c))
+    {
+        for flag in opt.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        words = &words[1..];
     }
-    let s = &args[0];
-    if all().contains(&s.as_str()) {
-        writeln!(stdout, "{s} is a shell builtin")?;
-        return Ok(());
+
+    let text = words.join(" ");
+    let mut text = if escapes {
+        let (expanded, stop) = printf::unescape(&text, true);
+        newline &= !stop;
+        expanded
+    } else {
+        text.into_bytes()
+    };
+    if newline {
+        text.push(b'\n');
+    }
+    stdout.write_all(&text)?;
+    Ok(0)
+}
+
+/// `printf [-v var] format [args...]` formats the arguments like printf(1) (see `printf::format`),
+/// reusing the format until they are used up. With `-v` the result is stored in `var` instead of printed.
+pub fn printf(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut rest = args;
+    let mut var = None;
+    loop {
+        match rest.first().map(String::as_str) {
+            Some("-v") => match rest.get(1) {
+                Some(name) if is_identifier(name) => var = Some(name),
+                Some(name) => return fail(stderr, format_args!("printf: `{}': not a valid identifier", name)),
+                None => return fail(stderr, format_args!("printf: -v: option requires an argument")),
+            },
+            Some("--") => {
+                rest = &rest[1..];
+                break;
+            }
+            _ => break,
+        }
+        rest = &rest[2..];
+    }
+    let Some((format, args)) = rest.split_first() else {
+        writeln!(stderr, "printf: usage: printf [-v var] format [arguments]")?;
+        return Ok(2);
+    };
+
+    let output = printf::format(format, args);
+    for error in &output.errors {
+        writeln!(stderr, "printf: {}", error)?;
+    }
+    match var {
+        Some(name) => {
+            if let Err(e) = state::set_var(name, OsStr::from_bytes(&output.text)) {
+                return fail(stderr, format_args!("printf: {name}: {e}"));
+            }
+        }
+        None => stdout.write_all(&output.text)?,
+    }
+    Ok(if output.errors.is_empty() { 0 } else { 1 })
+}
+
+/// `read [-rs] [-a array] [-d delim] [-n count] [-N count] [-p prompt] [-t timeout] [name...]` reads a line and
+/// splits it on `$IFS` into the names, the last one getting the rest of the line (`REPLY` gets the whole line
+/// if no name is given). `-r` keeps backslashes, `-a` stores all fields in an array, `-d` reads up to `delim`
+/// instead of a newline, `-n` stops after `count` characters and `-N` reads exactly `count` without splitting.
+/// `-p` prompts on a terminal, `-s` hides typed characters, `-t` gives up after `timeout` seconds (`-t 0` only
+/// tells whether input is waiting). Fails at end of input, and with status 142 on timeout.
+pub fn read(args: &[String], stdin: &mut dyn Input, _stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut opts = ReadOptions { raw: false, delim: b'\n', count: None, exact: false, deadline: None };
+    let (mut silent, mut array, mut prompt, mut timeout) = (false, None, None, None);
+    let mut names = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" || !arg.starts_with('-') || arg.len() < 2 {
+            names.extend(iter.clone().cloned());
+            if arg != "--" {
+                names.insert(0, arg.clone());
+            }
+            break;
+        }
+        for (i, flag) in arg.char_indices().skip(1) {
+            match flag {
+                'r' => opts.raw = true,
+                's' => silent = true,
+                'a' | 'd' | 'n' | 'N' | 'p' | 't' => {
+                    // the value is the rest of this word, or the next one
+                    let attached = &arg[i + 1..];
+                    let value = match iter.next() {
+                        _ if !attached.is_empty() => attached.to_string(),
+                        Some(value) => value.clone(),
+                        None => return fail(stderr, format_args!("read: -{flag}: option requires an argument")),
+                    };
+                    match flag {
+                        'a' => array = Some(value),
+                        'd' => opts.delim = value.bytes().next().unwrap_or(0),
+                        'p' => prompt = Some(value),
+                        't' => match value.parse::<f64>() {
+                            Ok(t) if t >= 0.0 => timeout = Some(t),
+                            _ => return fail(stderr, format_args!("read: {value}: invalid timeout specification")),
+                        },
+                        _ => match value.parse() {
+                            Ok(n) => {
+                                opts.count = Some(n);
+                                opts.exact = flag == 'N';
+                            }
+                            Err(_) => return fail(stderr, format_args!("read: {value}: invalid number")),
+                        },
+                    }
+                    break;
+                }
+                _ => return fail(stderr, format_args!("read: -{flag}: invalid option")),
+            }
+        }
+    }
+    if let Some(name) = array.iter().chain(&names).find(|name| !is_identifier(name)) {
+        return fail(stderr, format_args!("read: `{name}': not a valid identifier"));
+    }
+
+    if timeout == Some(0.0) {
+        return Ok(if stdin.wait(0) { 0 } else { 1 });
+    }
+    opts.deadline = timeout.map(|t| Instant::now() + Duration::from_secs_f64(t));
+    let terminal = stdin.is_terminal();
+    if let Some(prompt) = prompt
+        && terminal
+    {
+        write!(stderr, "{prompt}")?;
+        stderr.flush()?;
+    }
+    let result = if terminal {
+        let _mode = sys::read_mode(!silent, opts.count.is_some());
+        read_input(&mut Terminal, &opts)
+    } else {
+        read_input(stdin, &opts)
+    };
+    let ReadInput { chars, status, dropped_nul } = match result {
+        Ok(read) => read,
+        Err(e) => return fail(stderr, format_args!("read: read error: {}", describe(&e))),
+    };
+    if dropped_nul {
+        writeln!(stderr, "read: warning: ignored null byte in input")?;
+    }
+
+    let ifs = state::var("IFS").map_or_else(|| fields::DEFAULT_IFS.to_string(), |ifs| ifs.to_string_lossy().into_owned());
+    let stored = if let Some(array) = &array {
+        state::set_array(array, fields::split(&chars, &ifs, usize::MAX))
+    } else if names.is_empty() || opts.exact {
+        let name = names.first().map_or("REPLY", String::as_str);
+        let line: String = chars.iter().map(|&(c, _)| c).collect();
+        state::set_var(name, line)
+    } else {
+        let mut values = fields::split(&chars, &ifs, names.len()).into_iter();
+        names.iter().try_for_each(|name| state::set_var(name, values.next().unwrap_or_default()))
+    };
+    if let Err(e) = stored {
+        return fail(stderr, format_args!("read: {e}"));
+    }
+    Ok(status)
+}
+
+struct ReadOptions {
+    raw: bool,
+    delim: u8,
+    count: Option<usize>,
+    /// `-N`: the delimiter is an ordinary character.
+    exact: bool,
+    deadline: Option<Instant>,
+}
+
+/// What `read_input` got.
+struct ReadInput {
+    /// The chars, with whether each was escaped by a backslash.
+    chars: Vec<(char, bool)>,
+    /// The exit status `read` should have.
+    status: i32,
+    /// NUL bytes were dropped: no variable can hold one, so like bash `read` ignores them.
+    dropped_nul: bool,
+}
+
+/// Reads one byte at a time, so nothing after the delimiter is taken from a shared input.
+fn read_input(stdin: &mut dyn Input, opts: &ReadOptions) -> io::Result<ReadInput> {
+    let mut chars = Vec::new();
+    let mut utf8 = Vec::new();
+    let mut escaped = false;
+    let mut dropped_nul = false;
+    let status = loop {
+        if opts.count.is_some_and(|n| chars.len() >= n) {
+            break 0;
+        }
+        if let Some(deadline) = opts.deadline {
+            let left = deadline.saturating_duration_since(Instant::now()).as_millis();
+            if !stdin.wait(left.try_into().unwrap_or(i32::MAX)) {
+                break 142; // 128 + SIGALRM, as bash
+            }
+        }
+        let mut byte = [0u8];
+        match stdin.read(&mut byte) {
+            Ok(0) => break 1,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        let b = byte[0];
+        let literal = escaped;
+        if escaped {
+            escaped = false;
+            if b == b'\n' {
+                continue; // a backslash-newline continues the line
+            }
+        } else if !opts.raw && b == b'\\' {
+            escaped = true;
+            continue;
+        } else if b == opts.delim && !opts.exact {
+            break 0;
+        }
+        if b == 0 {
+            dropped_nul = true;
+            continue;
+        }
+        utf8.push(b);
+        if let Ok(s) = std::str::from_utf8(&utf8) {
+            chars.extend(s.chars().map(|c| (c, literal)));
+            utf8.clear();
+        } else if utf8.len() >= 4 {
+            chars.push(('\u{fffd}', false));
+            utf8.clear();
+        }
+    };
+    Ok(ReadInput { chars, status, dropped_nul })
+}
+
+/// A valid variable name: a letter or `_`, then letters, digits and `_`.
+fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `test expression` succeeds if the expression is true (see `conditional::test`); status 2 on a syntax error.
+pub fn test(args: &[String], _stdin: &mut dyn Input, _stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    test_status(CMD_TEST, args, stderr)
+}
+
+/// `[ expression ]` is `test expression`, with a mandatory closing `]`.
+pub fn bracket(args: &[String], _stdin: &mut dyn Input, _stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    match args.split_last() {
+        Some((last, args)) if last == "]" => test_status(CMD_BRACKET, args, stderr),
+        _ => {
+            writeln!(stderr, "[: missing `]'")?;
+            Ok(2)
+        }
     }
+}
 
-    match find_executable_in_path(s) {
-        Some(path) => writeln!(stdout, "{s} is {}", path.display())?,
-        None => writeln!(stderr, "{s}: not found")?,
+fn test_status(name: &str, args: &[String], stderr: &mut dyn Write) -> Result<i32> {
+    match conditional::test(args) {
+        Ok(true) => Ok(0),
+        Ok(false) => Ok(1),
+        Err(e) => {
+            writeln!(stderr, "{name}: {e}")?;
+            Ok(2)
+        }
     }
-    Ok(())
 }
 
-pub fn echo(args: &[String], stdout: &mut dyn Write, _stderr: &mut dyn Write) -> Result<()> {
-    writeln!(stdout, "{}", args.join(" "))
+/// `export NAME=value ...` sets environment variables (e.g. `PS1`, `PROMPT_COMMAND`), and `export NAME`
+/// exports a variable set by `read` or `printf -v`; without arguments lists the environment.
+pub fn export(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    if args.is_empty() {
+        let mut vars: Vec<(String, String)> = env::vars().collect();
+        vars.sort();
+        for (name, value) in vars {
+            writeln!(stdout, "declare -x {}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        return Ok(0);
+    }
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_identifier(name) {
+            writeln!(stderr, "export: `{}': not a valid identifier", arg)?;
+            status = 1;
+            continue;
+        }
+        if let Err(e) = state::export_var(name, value.map(OsStr::new)) {
+            writeln!(stderr, "export: {name}: {e}")?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `hash [-lr] [-p path] [-dt] [name ...]`: shows or edits the table of remembered
+/// command locations that are consulted before searching `PATH`; running a command adds it.
+pub fn hash(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut list = false;
+    let mut delete = false;
+    let mut show_path = false;
+    let mut path: Option<String> = None;
+    let mut names = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-r" => executables::rehash(),
+            "-l" => list = true,
+            "-d" => delete = true,
+            "-t" => show_path = true,
+            "-p" => match iter.next() {
+                Some(p) => path = Some(p.clone()),
+                None => return fail(stderr, format_args!("hash: -p: option requires an argument")),
+            },
+            opt if opt.starts_with('-') => return fail(stderr, format_args!("hash: {}: invalid option", opt)),
+            _ => names.push(arg.clone()),
+        }
+    }
+
+    if names.is_empty() {
+        if args.iter().any(|a| a == "-r") && !list {
+            return Ok(0);
+        }
+        let table = executables::hash_table();
+        if table.is_empty() {
+            writeln!(stderr, "hash: hash table empty")?;
+            return Ok(0);
+        }
+        if !list {
+            writeln!(stdout, "hits\tcommand")?;
+        }
+        for (name, path, hits) in table {
+            if list {
+                writeln!(stdout, "builtin hash -p {} {}", path.display(), name)?;
+            } else {
+                writeln!(stdout, "{:4}\t{}", hits, path.display())?;
+            }
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for name in &names {
+        if let Some(p) = &path {
+            executables::hash_path(name, p.into());
+        } else if delete {
+            if !executables::unhash(name) {
+                writeln!(stderr, "hash: {}: not found", name)?;
+                status = 1;
+            }
+        } else if show_path {
+            match executables::hashed(name) {
+                Some(p) if names.len() > 1 => writeln!(stdout, "{}\t{}", name, p.display())?,
+                Some(p) => writeln!(stdout, "{}", p.display())?,
+                None => {
+                    writeln!(stderr, "hash: {}: not found", name)?;
+                    status = 1;
+                }
+            }
+        } else if all().contains(&name.as_str()) {
+            // builtins are never hashed
+        } else if executables::hash(name).is_none() {
+            writeln!(stderr, "hash: {}: not found", name)?;
+            status = 1;
+        }
+    }
+    Ok(status)
 }
 
-pub fn history(args: &[String], stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<()> {
+/// `history [n]`, `history -c`, `history -d offset|start-end`, `history -s args`, `history -p args`,
+/// `history -r|-w|-a|-n [file]` (the file defaults to `$HISTFILE`), `history search [options] [text]`.
+pub fn history(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
     if let Some(first_arg) = args.first() {
         match first_arg.as_str() {
-            "-r" => {
-                if let Some(path_str) = args.get(1) {
-                    let path = Path::new(path_str);
-                    if let Err(e) = history::read_from_file(path) {
-                        writeln!(stderr, "history: {}: {}", path.display(), e)?;
-                    }
-                } else {
-                    writeln!(stderr, "history: -r: option requires an argument")?;
+            "-r" | "-w" | "-a" | "-n" => {
+                let Some(path_str) = args.get(1).cloned().or_else(|| env::var("HISTFILE").ok()) else {
+                    return fail(stderr, format_args!("history: {}: option requires an argument", first_arg));
+                };
+                let path = Path::new(&path_str);
+                let result = match first_arg.as_str() {
+                    "-r" => history::read_from_file(path).map(|_| ()),
+                    "-w" => history::write_to_file(path),
+                    "-a" => history::append_to_file(path),
+                    _ => history::read_new_from_file(path).map(|_| ()),
+                };
+                if let Err(e) = result {
+                    return fail(stderr, format_args!("history: {}: {}", path.display(), e));
                 }
             }
-            "-w" => {
-                if let Some(path_str) = args.get(1) {
-                    let path = Path::new(path_str);
-                    if let Err(e) = history::write_to_file(path) {
-                        writeln!(stderr, "history: {}: {}", path.display(), e)?;
-                    }
-                } else {
-                    writeln!(stderr, "history: -w: option requires an argument")?;
+            "-c" => history::clear(),
+            "search" => return history_search(&args[1..], stdout, stderr),
+            "-d" => {
+                let Some(offset) = args.get(1) else {
+                    return fail(stderr, format_args!("history: -d: option requires an argument"));
+                };
+                let len = history::len();
+                let range = match offset.get(1..).and_then(|rest| rest.find('-').map(|i| i + 1)) {
+                    Some(dash) => history_offset(&offset[..dash], len)
+                        .zip(history_offset(&offset[dash + 1..], len)),
+                    None => history_offset(offset, len).map(|i| (i, i)),
+                };
+                if !range.is_some_and(|(start, end)| history::delete_range(start, end)) {
+                    return fail(stderr, format_args!("history: {}: history position out of range", offset));
                 }
             }
-            "-a" => {
-                if let Some(path_str) = args.get(1) {
-                    let path = Path::new(path_str);
-                    if let Err(e) = history::append_to_file(path) {
-                        writeln!(stderr, "history: {}: {}", path.display(), e)?;
+            "-s" => {
+                // the `history -s` line itself is replaced by its arguments
+                history::remove_current();
+                if args.len() > 1 {
+                    history::add(&args[1..].join(" "));
+                }
+            }
+            "-p" => {
+                history::remove_current();
+                let entries = history::get_recent(history::len());
+                for arg in &args[1..] {
+                    match histexpand::expand(arg, &entries) {
+                        Ok(expansion) => writeln!(stdout, "{}", expansion.line)?,
+                        Err(e) => return fail(stderr, format_args!("history: {}", e)),
                     }
-                } else {
-                    writeln!(stderr, "history: -a: option requires an argument")?;
                 }
             }
             _ => {
                 // Not a flag, try to parse as a number for limit
                 match first_arg.parse::<usize>() {
                     Ok(n) => history::print(stdout, Some(n)),
-                    Err(_) => {
-                        writeln!(stderr, "history: {}: numeric argument required", first_arg)?;
-                    }
+                    Err(_) => return fail(stderr, format_args!("history: {}: numeric argument required", first_arg)),
                 }
             }
         }
@@ -108,32 +773,684 @@ pub fn history(args: &[String], stdout: &mut dyn Write, stderr: &mut dyn Write)
         // No arguments, print all history
         history::print(stdout, None);
     }
-    Ok(())
+    Ok(0)
 }
 
-pub fn pwd(_args: &[String], stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<()> {
-    match env::current_dir() {
-        Ok(cwd) => writeln!(stdout, "{}", cwd.display()),
-        Err(e) => writeln!(stderr, "pwd: {}", e),
+/// `history search [-d dir] [-D dir] [-s status] [-f] [-a time] [-b time] [text]` lists the commands
+/// run in `dir` (`-D`: or below it), with the given exit status (`-f`: any failure), after/before a time
+/// (epoch seconds or an age like `2h`, `3d`) and containing `text`.
+/// Searches the `$HISTDB` database if set, the in-memory history otherwise.
+fn history_search(args: &[String], stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut filter = histdb::Filter::default();
+    let mut text = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let opt = arg.as_str();
+        if !matches!(opt, "-d" | "-D" | "-s" | "-a" | "-b") {
+            match opt {
+                "-f" => filter.failed = true,
+                "--" => text.extend(iter.by_ref().cloned()),
+                _ if opt.starts_with('-') && opt.len() > 1 => {
+                    return fail(stderr, format_args!("history: search: {}: invalid option", opt));
+                }
+                _ => text.push(arg.clone()),
+            }
+            continue;
+        }
+        let Some(value) = iter.next() else {
+            return fail(stderr, format_args!("history: search: {}: option requires an argument", opt));
+        };
+        match opt {
+            "-d" | "-D" => {
+                filter.dir = Some(dirs::normalize(&dirs::cwd().join(value)).display().to_string());
+                filter.recursive = opt == "-D";
+            }
+            "-s" => match value.parse() {
+                Ok(status) => filter.status = Some(status),
+                Err(_) => return fail(stderr, format_args!("history: search: {}: numeric argument required", value)),
+            },
+            _ => {
+                let Some(time) = histdb::parse_time(value, sys::now()) else {
+                    return fail(stderr, format_args!("history: search: {}: invalid time", value));
+                };
+                if opt == "-a" { filter.since = Some(time) } else { filter.until = Some(time) }
+            }
+        }
+    }
+    if !text.is_empty() {
+        filter.text = Some(text.join(" "));
+    }
+
+    let records = match histdb::path() {
+        Some(path) => match histdb::read_all(&path) {
+            Ok(records) => records,
+            Err(e) => return fail(stderr, format_args!("history: {}: {}", path.display(), e)),
+        },
+        None => history::records(),
+    };
+    let time_format = env::var("HISTTIMEFORMAT").ok().filter(|f| !f.is_empty());
+    let time_format = time_format.as_deref().unwrap_or("%F %T ");
+    for record in records.iter().filter(|r| filter.matches(r)) {
+        let time = record.start.map_or("?? ".to_string(), |t| sys::strftime(time_format, t));
+        let status = record.status.map_or("-".to_string(), |s| s.to_string());
+        let duration = record.duration_ms.map_or("-".to_string(), |d| format!("{}.{:03}s", d / 1000, d % 1000));
+        let cwd = record.cwd.as_deref().map_or("-".to_string(), prompt::abbreviate_home);
+        writeln!(stdout, "{}{:>3}  {:>8}  {}  {}", time, status, duration, cwd, record.command)?;
     }
+    Ok(0)
 }
 
-pub fn cd(args: &[String], _stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<()> {
-    let cd_path = if args.is_empty() { "~" } else { &args[0] };
+/// A `history -d` offset as a 0-based index: `n` is the n-th entry, `-n` counts back from the end.
+fn history_offset(s: &str, len: usize) -> Option<usize> {
+    let n: i64 = s.parse().ok()?;
+    let index = if n < 0 { len as i64 + n } else { n - 1 };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
 
-    let path_str = if cd_path == "~" {
-        // Try to get home directory, fallback to current dir "."
-        env::var("HOME").unwrap_or_else(|_| ".".to_string())
+/// `pwd [-L|-P]`: the logical directory (`$PWD`, default) or the physical one with symlinks resolved.
+pub fn pwd(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut physical = false;
+    for arg in args {
+        match arg.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            _ => return fail(stderr, format_args!("pwd: {}: invalid option", arg)),
+        }
+    }
+    let cwd = if physical {
+        match env::current_dir() {
+            Ok(cwd) => cwd,
+            Err(e) => return fail(stderr, format_args!("pwd: {}", e)),
+        }
     } else {
-        cd_path.to_string()
+        dirs::cwd()
+    };
+    writeln!(stdout, "{}", cwd.display())?;
+    Ok(0)
+}
+
+/// `set -o vi`, `set -o emacs`, `set -o share_history` (and `+o` to turn an option off); `set -o` lists the options.
+/// `set +o vi` goes back to emacs mode, while `set +o emacs` changes nothing, as there is no mode without editing.
+/// The main loop picks up a changed edit mode before the next prompt.
+pub fn set(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut i = 0;
+    while i < args.len() {
+        let on = match args[i].as_str() {
+            "-o" => true,
+            "+o" => false,
+            other => return fail(stderr, format_args!("set: {}: invalid option", other)),
+        };
+        let Some(name) = args.get(i + 1) else {
+            let vi = state::vi_mode();
+            writeln!(stdout, "{:<15}\t{}", "emacs", if vi { "off" } else { "on" })?;
+            writeln!(stdout, "{:<15}\t{}", "share_history", if state::share_history() { "on" } else { "off" })?;
+            writeln!(stdout, "{:<15}\t{}", "vi", if vi { "on" } else { "off" })?;
+            return Ok(0);
+        };
+        match name.as_str() {
+            "vi" => state::set_vi_mode(on),
+            // the editor always has a mode: turning emacs off leaves it as it is, not in vi mode
+            "emacs" => if on { state::set_vi_mode(false) },
+            "share_history" => state::set_share_history(on),
+            _ => return fail(stderr, format_args!("set: {}: invalid option name", name)),
+        }
+        i += 2;
+    }
+    Ok(0)
+}
+
+/// `cd [-L|-P] [dir]`: with no `dir` goes to `$HOME`, `cd -` to `$OLDPWD` (printing it);
+/// a relative `dir` is looked up in `$CDPATH`. `-L` (default) keeps symlinks in `$PWD`, `-P` resolves them.
+pub fn cd(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut physical = false;
+    let mut operands = args.iter().map(String::as_str).peekable();
+    while let Some(&opt) = operands.peek() {
+        match opt {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => { operands.next(); break; }
+            _ => break,
+        }
+        operands.next();
+    }
+
+    let (target, print) = match operands.next() {
+        None => match env::var("HOME") {
+            Ok(home) => (PathBuf::from(home), false),
+            Err(_) => return fail(stderr, format_args!("cd: HOME not set")),
+        },
+        Some(dir) => match dir_operand(dir) {
+            Some(target) => target,
+            None => return fail(stderr, format_args!("cd: OLDPWD not set")),
+        },
+    };
+
+    match dirs::change_dir(&target, physical) {
+        Ok(new) => {
+            if print {
+                writeln!(stdout, "{}", new.display())?;
+            }
+            Ok(0)
+        }
+        Err(e) => match e.kind() {
+            io::ErrorKind::NotFound => fail(stderr, format_args!("cd: {}: No such file or directory", target.display())),
+            _ => fail(stderr, format_args!("cd: {}: {}", target.display(), e)),
+        },
+    }
+}
+
+/// Where `cd dir` or `pushd dir` goes: `-` is `$OLDPWD`, `~` at the start is `$HOME`, and a relative `dir`
+/// is looked up in `$CDPATH`. The flag is true if the result should be printed; `None` if `$OLDPWD` is unset.
+fn dir_operand(dir: &str) -> Option<(PathBuf, bool)> {
+    if dir == "-" {
+        return env::var("OLDPWD").ok().map(|old| (PathBuf::from(old), true));
+    }
+    let dir = match (dir.strip_prefix('~'), env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => format!("{home}{rest}"),
+        _ => dir.to_string(),
+    };
+    Some(dirs::search_cdpath(&dir))
+}
+
+/// `pushd dir` saves the current directory on the stack and changes to `dir` (expanded like `cd`'s, so
+/// `pushd -` goes to `$OLDPWD`); `pushd` swaps the top two
+/// entries and `pushd +N`/`-N` rotates entry N (counted from the left/right of `dirs`) to the top.
+/// Prints the stack like `dirs`.
+pub fn pushd(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let stack = dirs::stack();
+    let (result, target) = match args.first().map(String::as_str) {
+        None if stack.len() < 2 => return fail(stderr, format_args!("pushd: no other directory")),
+        None => (dirs::swap(), stack[1].clone()),
+        Some(arg) if is_stack_index(arg) => match stack_index(arg, stack.len()) {
+            Some(n) => (dirs::rotate(n), stack[n].clone()),
+            None => return fail(stderr, format_args!("pushd: {}: directory stack index out of range", arg)),
+        },
+        Some(dir) => {
+            let Some((target, _)) = dir_operand(dir) else {
+                return fail(stderr, format_args!("pushd: OLDPWD not set"));
+            };
+            (dirs::push(&target), target)
+        }
+    };
+    match result {
+        Ok(()) => print_stack(stdout, DirsFormat::Line, false),
+        Err(e) => fail(stderr, format_args!("pushd: {}: {}", target.display(), describe(&e))),
+    }
+}
+
+/// `popd` removes the top of the directory stack and changes to the new top;
+/// `popd +N`/`-N` removes entry N instead. Prints the stack like `dirs`.
+pub fn popd(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let stack = dirs::stack();
+    if stack.len() < 2 {
+        return fail(stderr, format_args!("popd: directory stack empty"));
+    }
+    let n = match args.first() {
+        None => 0,
+        Some(arg) if is_stack_index(arg) => match stack_index(arg, stack.len()) {
+            Some(n) => n,
+            None => return fail(stderr, format_args!("popd: {}: directory stack index out of range", arg)),
+        },
+        Some(arg) => return fail(stderr, format_args!("popd: {}: invalid argument", arg)),
+    };
+    match dirs::pop(n) {
+        Ok(()) => print_stack(stdout, DirsFormat::Line, false),
+        Err(e) => fail(stderr, format_args!("popd: {}: {}", stack[1].display(), describe(&e))),
+    }
+}
+
+/// `dirs [-clpv] [+N|-N]` shows the directory stack, current directory first, with `$HOME` as `~`
+/// unless `-l`. `-p` prints one entry per line, `-v` also numbers them, `-c` clears the stack.
+pub fn dirs(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let mut format = DirsFormat::Line;
+    let mut long = false;
+    let mut clear = false;
+    for arg in args {
+        match arg.as_str() {
+            "-c" => clear = true,
+            "-l" => long = true,
+            "-p" => format = DirsFormat::PerLine,
+            "-v" => format = DirsFormat::Numbered,
+            arg if is_stack_index(arg) => {
+                let stack = dirs::stack();
+                let Some(n) = stack_index(arg, stack.len()) else {
+                    return fail(stderr, format_args!("dirs: {}: directory stack index out of range", arg));
+                };
+                writeln!(stdout, "{}", display_dir(&stack[n], long))?;
+                return Ok(0);
+            }
+            _ => return fail(stderr, format_args!("dirs: {}: invalid option", arg)),
+        }
+    }
+    if clear {
+        dirs::clear_stack();
+        return Ok(0);
+    }
+    print_stack(stdout, format, long)
+}
+
+enum DirsFormat {
+    Line,
+    PerLine,
+    Numbered,
+}
+
+fn print_stack(stdout: &mut dyn Write, format: DirsFormat, long: bool) -> Result<i32> {
+    let stack: Vec<String> = dirs::stack().iter().map(|d| display_dir(d, long)).collect();
+    match format {
+        DirsFormat::Line => writeln!(stdout, "{}", stack.join(" "))?,
+        DirsFormat::PerLine => stack.iter().try_for_each(|d| writeln!(stdout, "{}", d))?,
+        DirsFormat::Numbered => stack.iter().enumerate().try_for_each(|(i, d)| writeln!(stdout, "{:2}  {}", i, d))?,
+    }
+    Ok(0)
+}
+
+fn display_dir(dir: &Path, long: bool) -> String {
+    let dir = dir.display().to_string();
+    if long { dir } else { prompt::abbreviate_home(&dir) }
+}
+
+/// `+N` or `-N`.
+fn is_stack_index(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with(['+', '-']) && arg[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The position in `dirs::stack()` of `+N` (from the left) or `-N` (from the right), if it exists.
+fn stack_index(arg: &str, len: usize) -> Option<usize> {
+    let n: usize = arg[1..].parse().ok()?;
+    if n >= len {
+        return None;
+    }
+    Some(if arg.starts_with('+') { n } else { len - 1 - n })
+}
+
+/// `j pattern...` changes to the most frecent directory visited with `cd` whose path contains the patterns
+/// in order (see `jump::matches`) and prints it. `j -l [pattern...]` and `j` alone list the candidates, best last.
+pub fn j(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let (list, words) = match args.first().map(String::as_str) {
+        Some("-l") => (true, &args[1..]),
+        Some(_) => (false, args),
+        None => (true, args),
     };
+    let now = sys::now();
+    let found = jump::matches(words, &jump::entries(), now);
+    if list {
+        for e in found.iter().rev() {
+            writeln!(stdout, "{:<10.1} {}", e.frecency(now), e.path.display())?;
+        }
+        return Ok(0);
+    }
+    let Some(best) = found.iter().find(|e| e.path != dirs::cwd()) else {
+        return fail(stderr, format_args!("j: {}: no matching directory", words.join(" ")));
+    };
+    match dirs::change_dir(&best.path, false) {
+        Ok(new) => {
+            writeln!(stdout, "{}", new.display())?;
+            Ok(0)
+        }
+        Err(e) => fail(stderr, format_args!("j: {}: {}", best.path.display(), describe(&e))),
+    }
+}
+
+/// An I/O error's message without the "(os error N)" suffix, as the shell prints it.
+fn describe(e: &io::Error) -> String {
+    let message = e.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+/// Options shared by `complete` and `compgen`: `-f -d -c -W wordlist -F name`,
+/// plus `complete`'s `-p` (print) and `-r` (remove).
+struct CompOptions {
+    spec: CompSpec,
+    print: bool,
+    remove: bool,
+    rest: Vec<String>,
+}
+
+fn parse_comp_options(cmd: &str, args: &[String]) -> std::result::Result<CompOptions, String> {
+    let mut opts = CompOptions { spec: CompSpec::default(), print: false, remove: false, rest: Vec::new() };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            opts.rest.extend(iter.cloned());
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            opts.rest.push(arg.clone());
+            opts.rest.extend(iter.cloned());
+            break;
+        }
+        for flag in arg[1..].chars() {
+            match flag {
+                'f' => opts.spec.files = true,
+                'd' => opts.spec.dirs = true,
+                'c' => opts.spec.commands = true,
+                'p' if cmd == CMD_COMPLETE => opts.print = true,
+                'r' if cmd == CMD_COMPLETE => opts.remove = true,
+                'W' => {
+                    let words = iter.next().ok_or(format!("{cmd}: -W: option requires an argument"))?;
+                    opts.spec.words = words.split_whitespace().map(str::to_string).collect();
+                }
+                'F' => {
+                    let name = iter.next().ok_or(format!("{cmd}: -F: option requires an argument"))?;
+                    opts.spec.function = Some(name.clone());
+                }
+                _ => return Err(format!("{cmd}: -{flag}: invalid option")),
+            }
+        }
+    }
+    Ok(opts)
+}
+
+/// `complete [-fdc] [-W wordlist] [-F name] name...` registers how arguments of the named
+/// commands are completed; `complete -p [name...]` prints specs, `complete -r [name...]` removes them.
+pub fn complete(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let opts = match parse_comp_options(CMD_COMPLETE, args) {
+        Ok(opts) => opts,
+        Err(e) => return fail(stderr, format_args!("{e}")),
+    };
+
+    let mut status = 0;
+    if opts.remove {
+        if opts.rest.is_empty() {
+            completion::unregister_all();
+        }
+        for name in &opts.rest {
+            if !completion::unregister(name) {
+                writeln!(stderr, "complete: {name}: no completion specification")?;
+                status = 1;
+            }
+        }
+        return Ok(status);
+    }
+
+    if opts.print || opts.rest.is_empty() {
+        if opts.rest.is_empty() {
+            for (name, spec) in completion::specs() {
+                writeln!(stdout, "{}", spec.to_command_line(&name))?;
+            }
+        }
+        for name in &opts.rest {
+            match completion::spec_for(name) {
+                Some(spec) => writeln!(stdout, "{}", spec.to_command_line(name))?,
+                None => {
+                    writeln!(stderr, "complete: {name}: no completion specification")?;
+                    status = 1;
+                }
+            }
+        }
+        return Ok(status);
+    }
+
+    for name in &opts.rest {
+        completion::register(name, opts.spec.clone());
+    }
+    Ok(0)
+}
+
+/// `compgen [-fdc] [-W wordlist] [-F name] [word]` prints the candidates a spec
+/// would offer for `word`, one per line; handy for testing `complete` specs.
+pub fn compgen(args: &[String], _stdin: &mut dyn Input, stdout: &mut dyn Write, stderr: &mut dyn Write) -> Result<i32> {
+    let opts = match parse_comp_options(CMD_COMPGEN, args) {
+        Ok(opts) => opts,
+        Err(e) => return fail(stderr, format_args!("{e}")),
+    };
+    let word = opts.rest.first().cloned().unwrap_or_default();
+    let line = word.clone();
+    let current = CurrentWord { start: 0, word, open_quote: None, words: Vec::new() };
+    let candidates = opts.spec.generate(&current, &line, line.len());
+    for candidate in &candidates {
+        writeln!(stdout, "{}", candidate.replacement)?;
+    }
+    Ok(if candidates.is_empty() { 1 } else { 0 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    /// Runs builtin `cmd` and returns its status, stdout and stderr.
+    fn run(cmd: &str, args: &[&str]) -> (i32, String, String) {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let status = run_builtin(cmd, &args, &mut io::empty(), &mut stdout, &mut stderr).unwrap().unwrap();
+        (status, String::from_utf8(stdout).unwrap(), String::from_utf8(stderr).unwrap())
+    }
+
+    #[test]
+    fn test_complete_print_round_trips() {
+        assert_eq!(run(CMD_COMPLETE, &["-d", "-W", "it's  a b", "-F", "comp_fn", "round_trip_cmd"]).0, 0);
+        let spec = completion::spec_for("round_trip_cmd").unwrap();
+        assert_eq!(spec.words, ["it's", "a", "b"]);
+        let (status, printed, _) = run(CMD_COMPLETE, &["-p", "round_trip_cmd"]);
+        assert_eq!(status, 0);
+
+        assert_eq!(run(CMD_COMPLETE, &["-r", "round_trip_cmd"]).0, 0);
+        assert_eq!(run(CMD_COMPLETE, &["-p", "round_trip_cmd"]).0, 1);
+        let Command::SimpleCommand(cmd, args) = parse(printed.trim_end()) else {
+            panic!("{printed}");
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        assert_eq!(run(&cmd, &args).0, 0);
+        assert_eq!(completion::spec_for("round_trip_cmd"), Some(spec));
+        completion::unregister("round_trip_cmd");
+    }
+
+    #[test]
+    fn test_compgen_word_list() {
+        assert_eq!(run(CMD_COMPGEN, &["-W", "start stop status", "sta"]), (0, "start\nstatus\n".to_string(), String::new()));
+        assert_eq!(run(CMD_COMPGEN, &["-W", "start stop", "x"]).0, 1);
+        assert_eq!(run(CMD_COMPGEN, &["-W"]).0, 1);
+    }
+
+    #[test]
+    fn test_stack_index() {
+        assert!(is_stack_index("+0") && is_stack_index("-12"));
+        assert!(!is_stack_index("+") && !is_stack_index("-l") && !is_stack_index("3"));
+        // `+N` counts from the left of `dirs`, `-N` from the right
+        assert_eq!(stack_index("+0", 3), Some(0));
+        assert_eq!(stack_index("+2", 3), Some(2));
+        assert_eq!(stack_index("+3", 3), None);
+        assert_eq!(stack_index("-0", 3), Some(2));
+        assert_eq!(stack_index("-2", 3), Some(0));
+        assert_eq!(stack_index("-3", 3), None);
+        assert_eq!(stack_index("+99999999999999999999", 3), None);
+    }
+
+    #[test]
+    fn test_directory_stack() {
+        // the working directory and the variables below are process-wide
+        let _env = executables::ENV_LOCK.lock().unwrap();
+        let root = env::temp_dir().join(format!("shell_dirs_test_{}", std::process::id()));
+        for d in ["a", "b", "c"] {
+            std::fs::create_dir_all(root.join(d)).unwrap();
+        }
+        let saved: Vec<(&str, Option<std::ffi::OsString>)> =
+            ["PWD", "OLDPWD", "HOME", "JUMPFILE"].into_iter().map(|v| (v, env::var_os(v))).collect();
+        let original_dir = env::current_dir().unwrap();
+        unsafe {
+            env::set_var("HOME", root.join("home"));
+            env::set_var("JUMPFILE", root.join("jumps"));
+        }
+        dirs::clear_stack();
+        dirs::change_dir(&root, false).unwrap();
+
+        let r = root.display().to_string();
+        let line = |ds: &[&str]| -> String {
+            let dirs: Vec<String> = ds.iter().map(|d| if d.is_empty() { r.clone() } else { format!("{r}/{d}") }).collect();
+            dirs.join(" ") + "\n"
+        };
+        let cd = |d: &str| run(CMD_PUSHD, &[&format!("{r}/{d}")]);
+        let out = |(status, stdout, _): (i32, String, String)| { assert_eq!(status, 0); stdout };
+
+        assert_eq!(out(cd("a")), line(&["a", ""]));
+        assert_eq!(out(cd("b")), line(&["b", "a", ""]));
+        assert_eq!(out(cd("c")), line(&["c", "b", "a", ""]));
+        assert_eq!(out(run(CMD_DIRS, &["-v", "-l"])), format!(" 0  {r}/c\n 1  {r}/b\n 2  {r}/a\n 3  {r}\n"));
+        assert_eq!(out(run(CMD_DIRS, &["-p"])), format!("{r}/c\n{r}/b\n{r}/a\n{r}\n"));
+        // rotations bring entry N to the top and change to it
+        assert_eq!(out(run(CMD_PUSHD, &["+1"])), line(&["b", "a", "", "c"]));
+        assert_eq!(dirs::cwd(), root.join("b"));
+        assert_eq!(out(run(CMD_PUSHD, &["-1"])), line(&["", "c", "b", "a"]));
+        assert_eq!(dirs::cwd(), root);
+        assert_eq!(out(run(CMD_PUSHD, &[])), line(&["c", "", "b", "a"]));
+        assert_eq!(dirs::cwd(), root.join("c"));
+        assert_eq!(out(run(CMD_DIRS, &["+2"])), format!("{r}/b\n"));
+        assert_eq!(out(run(CMD_DIRS, &["-0"])), format!("{r}/a\n"));
+        assert_eq!(run(CMD_PUSHD, &["+4"]).0, 1);
+        assert_eq!(run(CMD_DIRS, &["-4"]).0, 1);
+        // popping below the top keeps the directory, popping the top changes to the next
+        assert_eq!(out(run(CMD_POPD, &["+1"])), line(&["c", "b", "a"]));
+        assert_eq!(out(run(CMD_POPD, &["-0"])), line(&["c", "b"]));
+        assert_eq!(dirs::cwd(), root.join("c"));
+        assert_eq!(out(run(CMD_POPD, &[])), line(&["b"]));
+        assert_eq!(dirs::cwd(), root.join("b"));
+        assert_eq!(env::var_os("PWD"), Some(root.join("b").into_os_string()));
+        assert_eq!(run(CMD_POPD, &[]), (1, String::new(), "popd: directory stack empty\n".to_string()));
+        assert_eq!(run(CMD_PUSHD, &[]), (1, String::new(), "pushd: no other directory\n".to_string()));
+        assert_eq!(run(CMD_DIRS, &["-x"]).0, 1);
+        out(cd("a"));
+        assert_eq!(out(run(CMD_DIRS, &["-c"])), "");
+        assert_eq!(out(run(CMD_DIRS, &[])), line(&["a"]));
+        // `~` and `-` work as for `cd`
+        std::fs::create_dir_all(root.join("home/x")).unwrap();
+        assert_eq!(out(run(CMD_PUSHD, &["~/x"])), format!("~/x {r}/a\n"));
+        assert_eq!(dirs::cwd(), root.join("home/x"));
+        assert_eq!(out(run(CMD_PUSHD, &["-"])), format!("{r}/a ~/x {r}/a\n"));
+        assert_eq!(dirs::cwd(), root.join("a"));
+
+        env::set_current_dir(original_dir).unwrap();
+        for (var, value) in saved {
+            match value {
+                Some(value) => unsafe { env::set_var(var, value) },
+                None => unsafe { env::remove_var(var) },
+            }
+        }
+        _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_set_edit_mode() {
+        let set = |args: &[&str]| assert_eq!(run(CMD_SET, args), (0, String::new(), String::new()));
+        set(&["+o", "emacs"]);
+        assert!(!state::vi_mode());
+        set(&["-o", "vi"]);
+        assert!(state::vi_mode());
+        set(&["+o", "emacs"]);
+        assert!(state::vi_mode());
+        set(&["+o", "vi"]);
+        assert!(!state::vi_mode());
+        set(&["+o", "vi"]);
+        assert!(!state::vi_mode());
+        assert!(run(CMD_SET, &["-o"]).1.starts_with("emacs          \ton\n"));
+    }
 
-    let path = Path::new(&path_str);
-    if let Err(e) = env::set_current_dir(path) {
-        match e.kind() {
-            io::ErrorKind::NotFound => writeln!(stderr, "cd: {}: No such file or directory", path.display())?,
-            _ => writeln!(stderr, "cd: {}: {}", path.display(), e)?,
+    #[test]
+    fn test_bypass() {
+        let simple = |cmd: &str, args: &[&str]| {
+            Command::SimpleCommand(cmd.to_string(), args.iter().map(|a| a.to_string()).collect())
+        };
+        assert_eq!(bypass(parse("command ls -l")), simple("ls", &["-l"]));
+        assert_eq!(bypass(parse("command -p -- ls -l")), simple("ls", &["-l"]));
+        assert_eq!(bypass(parse("command -p -p ls")), simple("ls", &[]));
+        assert_eq!(bypass(parse("command -- -x")), simple("command", &["--", "-x"]));
+        assert_eq!(bypass(parse("command -v ls")), simple("command", &["-v", "ls"]));
+        assert_eq!(bypass(parse("command -p")), simple("command", &["-p"]));
+        assert_eq!(bypass(parse("command command ls")), simple("command", &["ls"]));
+        assert_eq!(
+            bypass(parse("command ls | command -p wc")),
+            Command::PipeCommand(vec![simple("ls", &[]), simple("wc", &[])])
+        );
+    }
+
+    #[test]
+    fn test_type_and_command() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _env = executables::ENV_LOCK.lock().unwrap();
+        let root = env::temp_dir().join(format!("shell_type_test_{}", std::process::id()));
+        let (first, second) = (root.join("first"), root.join("second"));
+        let script = "#!/bin/sh\necho \"out $1\"\necho err >&2\nexit 3\n";
+        for (dir, name) in [(&first, "tool"), (&first, "echo"), (&second, "tool")] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join(name), script).unwrap();
+            std::fs::set_permissions(dir.join(name), std::fs::Permissions::from_mode(0o755)).unwrap();
         }
+        let original_path = env::var_os("PATH");
+        unsafe { env::set_var("PATH", env::join_paths([&first, &second]).unwrap()); }
+        let (f, s) = (first.display().to_string(), second.display().to_string());
+        let ok = |stdout: String| (0, stdout, String::new());
+
+        assert_eq!(run(CMD_TYPE, &["tool"]), ok(format!("tool is {f}/tool\n")));
+        assert_eq!(run(CMD_TYPE, &["-a", "tool"]), ok(format!("tool is {f}/tool\ntool is {s}/tool\n")));
+        assert_eq!(run(CMD_TYPE, &["-a", "echo"]), ok(format!("echo is a shell builtin\necho is {f}/echo\n")));
+        assert_eq!(run(CMD_TYPE, &["-t", "tool", "echo", "[["]), ok("file\nbuiltin\nkeyword\n".to_string()));
+        assert_eq!(run(CMD_TYPE, &["-p", "echo", "tool"]), ok(format!("{f}/tool\n")));
+        assert_eq!(run(CMD_TYPE, &["-P", "echo"]), ok(format!("{f}/echo\n")));
+        assert_eq!(run(CMD_TYPE, &["-ap", "tool"]), ok(format!("{f}/tool\n{s}/tool\n")));
+        assert_eq!(run(CMD_TYPE, &["missing", "tool"]), (1, format!("tool is {f}/tool\n"), "missing: not found\n".to_string()));
+        assert_eq!(run(CMD_TYPE, &["-t", "missing"]), (1, String::new(), String::new()));
+        assert_eq!(run(CMD_TYPE, &["-x", "tool"]), (1, String::new(), "type: -x: invalid option\n".to_string()));
+
+        assert_eq!(run(CMD_COMMAND, &["-v", "echo", "tool", "missing"]), (1, format!("echo\n{f}/tool\n"), String::new()));
+        assert_eq!(run(CMD_COMMAND, &["-V", "tool"]), ok(format!("tool is {f}/tool\n")));
+        // the child's output goes to the builtin's writers, not the shell's
+        assert_eq!(run(CMD_COMMAND, &["tool", "a"]), (3, "out a\n".to_string(), "err\n".to_string()));
+        assert_eq!(run(CMD_COMMAND, &["-p", "--", "tool"]), (3, "out \n".to_string(), "err\n".to_string()));
+        assert_eq!(run(CMD_COMMAND, &["echo", "hi"]), ok("hi\n".to_string()));
+        assert_eq!(run(CMD_COMMAND, &["missing"]), (127, String::new(), "missing: command not found\n".to_string()));
+        assert_eq!(run(CMD_COMMAND, &["-z"]), (1, String::new(), "command: -z: invalid option\n".to_string()));
+        assert_eq!(run(CMD_COMMAND, &[]), ok(String::new()));
+
+        match original_path {
+            Some(path) => unsafe { env::set_var("PATH", path); },
+            None => unsafe { env::remove_var("PATH"); },
+        }
+        _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_printf_v_refuses_null_bytes() {
+        assert_eq!(run(CMD_PRINTF, &["-v", "PRINTF_V_TEST", r"a\xffb"]), (0, String::new(), String::new()));
+        assert_eq!(state::var("PRINTF_V_TEST"), Some(OsStr::from_bytes(b"a\xffb").to_os_string()));
+        let (status, _, stderr) = run(CMD_PRINTF, &["-v", "PRINTF_V_TEST", r"a\0b"]);
+        assert_eq!((status, stderr.as_str()), (1, "printf: PRINTF_V_TEST: value contains a null byte\n"));
+        assert_eq!(state::var("PRINTF_V_TEST"), Some(OsStr::from_bytes(b"a\xffb").to_os_string()));
+    }
+
+    #[test]
+    fn test_read_drops_null_bytes() {
+        let args = ["-r".to_string(), "READ_NUL_A".to_string(), "READ_NUL_B".to_string()];
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut input = io::Cursor::new(b"a\0b c\0\nnext".to_vec());
+        assert_eq!(read(&args, &mut input, &mut stdout, &mut stderr).unwrap(), 0);
+        assert_eq!(String::from_utf8(stderr).unwrap(), "read: warning: ignored null byte in input\n");
+        assert_eq!((state::var("READ_NUL_A").unwrap(), state::var("READ_NUL_B").unwrap()), ("ab".into(), "c".into()));
+    }
+
+    #[test]
+    fn test_read_does_not_export() {
+        let _guard = executables::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut input = io::Cursor::new(b"one two\nthree\n".to_vec());
+        let args = ["READ_LOCAL".to_string()];
+        assert_eq!(read(&args, &mut input, &mut Vec::new(), &mut Vec::new()).unwrap(), 0);
+        let args = ["-a".to_string(), "READ_LOCAL_ARRAY".to_string()];
+        assert_eq!(read(&args, &mut input, &mut Vec::new(), &mut Vec::new()).unwrap(), 0);
+        assert_eq!(state::var("READ_LOCAL"), Some("one two".into()));
+        assert_eq!(state::array("READ_LOCAL_ARRAY").unwrap(), ["three"]);
+        assert_eq!((env::var_os("READ_LOCAL"), env::var_os("READ_LOCAL_ARRAY")), (None, None));
+
+        assert_eq!(run(CMD_EXPORT, &["READ_LOCAL"]), (0, String::new(), String::new()));
+        assert_eq!(env::var_os("READ_LOCAL"), Some("one two".into()));
+        // an exported variable stays exported when read sets it again
+        let mut input = io::Cursor::new(b"four\n".to_vec());
+        let args = ["READ_LOCAL".to_string()];
+        assert_eq!(read(&args, &mut input, &mut Vec::new(), &mut Vec::new()).unwrap(), 0);
+        assert_eq!(env::var_os("READ_LOCAL"), Some("four".into()));
+        unsafe { env::remove_var("READ_LOCAL"); }
     }
-    Ok(())
 }
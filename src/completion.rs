@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use is_executable::IsExecutable;
+use rustyline::completion::Pair;
+
+use crate::executables::{find_executable_in_path, get_all_executables};
+use crate::external::prepare_unix_command;
+use crate::parse::{self, TokenKind};
+use crate::builtins;
+use crate::state;
+
+/// What kind of paths a path completion should offer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathKind {
+    /// Files and directories (argument position).
+    Any,
+    /// Only directories, e.g. for `cd`.
+    Dirs,
+    /// Executables and directories (a command name containing `/`).
+    Executables,
+}
+
+/// The word under the cursor, as the parser sees it.
+#[derive(Debug, PartialEq)]
+pub struct CurrentWord {
+    /// Byte offset where the word (including any opening quote) starts.
+    pub start: usize,
+    /// The word with quotes and escapes removed.
+    pub word: String,
+    /// The quote the word is still inside of, if any.
+    pub open_quote: Option<char>,
+    /// The words of the simple command before this one, starting with the command name.
+    pub words: Vec<String>,
+}
+
+impl CurrentWord {
+    /// The first word of the line or the first word after `|`, `&&` or `||`.
+    pub fn is_command_position(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The command name of the simple command the word belongs to, unless the word is that name.
+    pub fn command(&self) -> Option<&str> {
+        self.words.first().map(String::as_str)
+    }
+}
+
+/// Finds the word ending at `pos` using the parser's lexer, so quotes and escapes
+/// (`my\ file`, `'my fi`) are handled the same way as when the line is run.
+pub fn current_word(line: &str, pos: usize) -> CurrentWord {
+    let lexed = parse::lex(&line[..pos]);
+    let mut tokens = lexed.tokens;
+
+    let (start, word, open_quote) = match tokens.last() {
+        Some(t) if t.kind == TokenKind::Word && t.end == pos => {
+            let t = tokens.pop().unwrap();
+            (t.start, t.text, lexed.open_quote)
+        }
+        _ => (pos, String::new(), None),
+    };
+
+    let first_word = tokens.iter().rposition(|t| t.kind == TokenKind::Operator).map_or(0, |i| i + 1);
+    let words = tokens.drain(first_word..).map(|t| t.text).collect();
+
+    CurrentWord { start, word, open_quote, words }
+}
+
+/// Quotes a completed value the way the word was started: inside `'...'` or `"..."`
+/// if it was opened with that quote, with backslash escapes otherwise.
+/// A finished word (not a directory) is closed and followed by a space.
+pub fn quote_replacement(value: &str, open_quote: Option<char>) -> String {
+    let finished = !value.ends_with('/');
+    let mut quoted = match open_quote {
+        Some('\'') => format!("'{}", value.replace('\'', "'\\''")),
+        Some(_) => {
+            let mut s = String::from("\"");
+            for c in value.chars() {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    s.push('\\');
+                }
+                s.push(c);
+            }
+            s
+        }
+        None => {
+            let mut s = String::new();
+            for c in value.chars() {
+                if " \t\n'\"\\$`|&;<>()*?[]{}#!".contains(c) {
+                    s.push('\\');
+                }
+                s.push(c);
+            }
+            s
+        }
+    };
+    if finished {
+        if let Some(q) = open_quote {
+            quoted.push(q);
+        }
+        quoted.push(' ');
+    }
+    quoted
+}
+
+/// Command names (builtins and PATH executables) starting with `word`.
+pub fn complete_command(word: &str, builtins: &[&str], system_commands: &[String]) -> Vec<Pair> {
+    let builtin_matches = builtins.iter()
+        .filter(|c| c.starts_with(word))
+        .map(|&c| c.to_string());
+
+    let system_matches = system_commands.iter()
+        .filter(|c| c.starts_with(word))
+        .cloned();
+
+    let mut names: Vec<String> = builtin_matches.chain(system_matches).collect();
+    names.sort();
+    names.dedup();
+    names.into_iter()
+        .map(|cmd| Pair { display: cmd.clone(), replacement: cmd })
+        .collect()
+}
+
+/// Paths starting with `word`, relative to the current directory unless `word` is absolute.
+/// Directories get a trailing `/` (so completion can continue into them);
+/// hidden entries are offered only when the file name part of `word` starts with `.`.
+/// Replacements are unquoted, see `quote_replacement`.
+pub fn complete_path(word: &str, kind: PathKind) -> Vec<Pair> {
+    let (dir_part, file_prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let dir = if dir_part.is_empty() { PathBuf::from(".") } else { expand_tilde(dir_part) };
+
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<Pair> = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if !name.starts_with(file_prefix) || (name.starts_with('.') && !file_prefix.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        let is_dir = path.is_dir(); // follows symlinks
+        match kind {
+            PathKind::Dirs if !is_dir => continue,
+            PathKind::Executables if !is_dir && !path.is_executable() => continue,
+            _ => {}
+        }
+        let (display, replacement) = if is_dir {
+            (format!("{name}/"), format!("{dir_part}{name}/"))
+        } else {
+            (name.to_string(), format!("{dir_part}{name}"))
+        };
+        candidates.push(Pair { display, replacement });
+    }
+    candidates.sort_by(|a, b| a.display.cmp(&b.display));
+    candidates
+}
+
+/// A completion spec registered with `complete` for a command name
+/// (or given directly to `compgen`).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompSpec {
+    /// `-W wordlist`, already split on whitespace
+    pub words: Vec<String>,
+    /// `-F name`: a command run to produce the candidates. This shell has no functions,
+    /// so `name` is a builtin or an executable; it gets bash's arguments
+    /// (command, current word, previous word) and `COMP_WORDS` (newline-separated),
+    /// `COMP_CWORD`, `COMP_LINE`, `COMP_POINT` in its environment (a builtin: as shell variables for that run).
+    /// Each line it prints is one `COMPREPLY` entry.
+    pub function: Option<String>,
+    /// `-f`
+    pub files: bool,
+    /// `-d`
+    pub dirs: bool,
+    /// `-c`
+    pub commands: bool,
+}
+
+static SPECS: LazyLock<Mutex<HashMap<String, CompSpec>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn register(name: &str, spec: CompSpec) {
+    SPECS.lock().unwrap().insert(name.to_string(), spec);
+}
+
+/// Removes the spec for `name`; returns false if there was none.
+pub fn unregister(name: &str) -> bool {
+    SPECS.lock().unwrap().remove(name).is_some()
+}
+
+pub fn unregister_all() {
+    SPECS.lock().unwrap().clear();
+}
+
+pub fn spec_for(name: &str) -> Option<CompSpec> {
+    SPECS.lock().unwrap().get(name).cloned()
+}
+
+/// All registered specs, sorted by command name.
+pub fn specs() -> Vec<(String, CompSpec)> {
+    let mut all: Vec<(String, CompSpec)> = SPECS.lock().unwrap()
+        .iter()
+        .map(|(name, spec)| (name.clone(), spec.clone()))
+        .collect();
+    all.sort_by(|a, b| a.0.cmp(&b.0));
+    all
+}
+
+impl CompSpec {
+    /// The `complete` command line that recreates this spec, as printed by `complete -p`.
+    pub fn to_command_line(&self, name: &str) -> String {
+        let mut line = String::from("complete");
+        if self.files {
+            line.push_str(" -f");
+        }
+        if self.dirs {
+            line.push_str(" -d");
+        }
+        if self.commands {
+            line.push_str(" -c");
+        }
+        if !self.words.is_empty() {
+            line.push_str(&format!(" -W '{}'", self.words.join(" ").replace('\'', "'\\''")));
+        }
+        if let Some(function) = &self.function {
+            line.push_str(&format!(" -F {function}"));
+        }
+        line.push(' ');
+        line.push_str(name);
+        line
+    }
+
+    /// Candidates for `current` (unquoted replacements, see `quote_replacement`).
+    /// `line` and `pos` are passed on to a `-F` completer as `COMP_LINE` and `COMP_POINT`.
+    pub fn generate(&self, current: &CurrentWord, line: &str, pos: usize) -> Vec<Pair> {
+        let word = current.word.as_str();
+        let mut candidates = Vec::new();
+
+        if self.commands {
+            candidates.extend(complete_command(word, &builtins::all(), &get_all_executables()));
+        }
+        if self.files {
+            candidates.extend(complete_path(word, PathKind::Any));
+        } else if self.dirs {
+            candidates.extend(complete_path(word, PathKind::Dirs));
+        }
+        candidates.extend(self.words.iter()
+            .filter(|w| w.starts_with(word))
+            .map(|w| Pair { display: w.clone(), replacement: w.clone() }));
+        if let Some(function) = &self.function {
+            candidates.extend(run_completer(function, current, line, pos).into_iter()
+                .map(|w| Pair { display: w.clone(), replacement: w }));
+        }
+
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        candidates.dedup_by(|a, b| a.replacement == b.replacement);
+        candidates
+    }
+}
+
+/// Runs a `-F` completer and returns its output lines (`COMPREPLY`).
+fn run_completer(name: &str, current: &CurrentWord, line: &str, pos: usize) -> Vec<String> {
+    let mut comp_words = current.words.clone();
+    comp_words.push(current.word.clone());
+    let command = current.command().unwrap_or_default().to_string();
+    let previous = current.words.last().cloned().unwrap_or_default();
+    let args = [command, current.word.clone(), previous];
+    let env_vars = [
+        ("COMP_WORDS", comp_words.join("\n")),
+        ("COMP_CWORD", current.words.len().to_string()),
+        ("COMP_LINE", line.to_string()),
+        ("COMP_POINT", pos.to_string()),
+    ];
+
+    let output = if builtins::all().contains(&name) {
+        // shell variables for the builtin's run only, so values the user has for them survive
+        let mut stdout = Vec::new();
+        state::with_vars(&env_vars, || {
+            builtins::run_builtin(name, &args, &mut std::io::empty(), &mut stdout, &mut std::io::sink())
+        });
+        stdout
+    } else if let Some(path) = find_executable_in_path(name) {
+        match prepare_unix_command(&path, name, &args).envs(env_vars).output() {
+            Ok(output) => output.stdout,
+            Err(_) => return Vec::new(),
+        }
+    } else {
+        return Vec::new();
+    };
+
+    String::from_utf8_lossy(&output)
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// `~/src` -> `$HOME/src`
+fn expand_tilde(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix('~')
+        && (rest.is_empty() || rest.starts_with('/'))
+        && let Ok(home) = env::var("HOME")
+    {
+        return Path::new(&home).join(rest.trim_start_matches('/'));
+    }
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_word_in_open_quote() {
+        let line = "cat 'my fi";
+        let current = current_word(line, line.len());
+        assert_eq!(current, CurrentWord {
+            start: 4,
+            word: "my fi".to_string(),
+            open_quote: Some('\''),
+            words: vec!["cat".to_string()],
+        });
+    }
+
+    #[test]
+    fn test_current_word_after_pipe_is_a_command() {
+        let line = "ls | gr";
+        let current = current_word(line, line.len());
+        assert_eq!(current.start, 5);
+        assert_eq!(current.word, "gr");
+        assert!(current.is_command_position());
+    }
+
+    #[test]
+    fn test_current_word_after_space_is_empty() {
+        let line = "cat ";
+        let current = current_word(line, line.len());
+        assert_eq!(current.start, 4);
+        assert_eq!(current.word, "");
+        assert_eq!(current.command(), Some("cat"));
+    }
+
+    #[test]
+    fn test_quote_replacement() {
+        assert_eq!(quote_replacement("my file.txt", None), "my\\ file.txt ");
+        assert_eq!(quote_replacement("my file.txt", Some('\'')), "'my file.txt' ");
+        assert_eq!(quote_replacement("my dir/", Some('"')), "\"my dir/");
+        assert_eq!(
+            quote_replacement("exe with 'single quotes'", None),
+            "exe\\ with\\ \\'single\\ quotes\\' "
+        );
+        assert_eq!(quote_replacement("it's", Some('\'')), "'it'\\''s' ");
+    }
+
+    #[test]
+    fn test_complete_path_hidden_files_and_dirs() {
+        let dir = env::temp_dir().join(format!("shell_complete_path_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join(".config")).unwrap();
+        fs::write(dir.join("visible.txt"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let base = format!("{}/", dir.display());
+        let complete = |word: &str, kind| -> Vec<(String, String)> {
+            complete_path(word, kind).into_iter().map(|p| (p.display, p.replacement)).collect()
+        };
+
+        let all = complete(&base, PathKind::Any);
+        let visible = complete(&format!("{base}.h"), PathKind::Any);
+        let hidden = complete(&format!("{base}."), PathKind::Any);
+        let dirs = complete(&base, PathKind::Dirs);
+        _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(all, [
+            ("sub/".to_string(), format!("{base}sub/")),
+            ("visible.txt".to_string(), format!("{base}visible.txt")),
+        ]);
+        assert_eq!(visible, [(".hidden".to_string(), format!("{base}.hidden"))]);
+        assert_eq!(hidden, [
+            (".config/".to_string(), format!("{base}.config/")),
+            (".hidden".to_string(), format!("{base}.hidden")),
+        ]);
+        assert_eq!(dirs, [("sub/".to_string(), format!("{base}sub/"))]);
+    }
+
+    #[test]
+    fn test_spec_registration_and_word_list() {
+        let spec = CompSpec { words: vec!["stop".into(), "start".into(), "status".into()], ..Default::default() };
+        register("spec_test_cmd", spec.clone());
+        assert_eq!(spec_for("spec_test_cmd"), Some(spec.clone()));
+        assert!(specs().iter().any(|(name, s)| name == "spec_test_cmd" && *s == spec));
+
+        let line = "spec_test_cmd sta";
+        let current = current_word(line, line.len());
+        let names: Vec<String> = spec.generate(&current, line, line.len()).into_iter().map(|p| p.replacement).collect();
+        assert_eq!(names, ["start", "status"]);
+
+        assert!(unregister("spec_test_cmd"));
+        assert!(!unregister("spec_test_cmd"));
+        assert_eq!(spec_for("spec_test_cmd"), None);
+    }
+
+    #[test]
+    fn test_function_completers() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _env = crate::executables::ENV_LOCK.lock().unwrap();
+        let line = "tool --fo";
+        let current = current_word(line, line.len());
+        unsafe { env::set_var("COMP_LINE", "kept"); }
+
+        // a builtin gets the command, word and previous word as arguments
+        let spec = CompSpec { function: Some("echo".into()), ..Default::default() };
+        let names: Vec<String> = spec.generate(&current, line, line.len()).into_iter().map(|p| p.replacement).collect();
+        assert_eq!(names, ["tool --fo tool"]);
+        assert_eq!(env::var("COMP_LINE").as_deref(), Ok("kept"));
+        assert_eq!(state::var("COMP_POINT"), None);
+
+        // an external command gets the COMP_* variables in its environment
+        let dir = env::temp_dir().join(format!("shell_completer_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("completer_test_fn");
+        fs::write(&script, "#!/bin/sh\necho \"$1 $2 $3\"\necho \"$COMP_CWORD:$COMP_POINT:$COMP_LINE\"\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let original_path = env::var_os("PATH");
+        unsafe { env::set_var("PATH", &dir); }
+        let spec = CompSpec { function: Some("completer_test_fn".into()), ..Default::default() };
+        let names: Vec<String> = spec.generate(&current, line, line.len()).into_iter().map(|p| p.replacement).collect();
+        unsafe {
+            match original_path {
+                Some(path) => env::set_var("PATH", path),
+                None => env::remove_var("PATH"),
+            }
+            env::remove_var("COMP_LINE");
+        }
+        _ = fs::remove_dir_all(&dir);
+        assert_eq!(names, ["1:9:tool --fo", "tool --fo tool"]);
+    }
+
+    #[test]
+    fn test_spec_command_line() {
+        let spec = CompSpec { words: vec!["it's".into(), "b".into()], function: Some("f".into()), dirs: true, ..Default::default() };
+        assert_eq!(spec.to_command_line("cmd"), "complete -d -W 'it'\\''s b' -F f cmd");
+    }
+}
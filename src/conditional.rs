@@ -0,0 +1,280 @@
+//! Conditional expressions: the arguments of the `test` and `[` builtins and the `[[ ]]` command.
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+use crate::parse::ConditionalExpr;
+use crate::regex::Regex;
+use crate::{pattern, state, sys};
+
+/// Operators taking one operand. In `test`, `-a` and `-o` are also the binary "and" and "or".
+pub const UNARY_OPERATORS: &[&str] = &[
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-n", "-o", "-p", "-r", "-s", "-t", "-u", "-v", "-w",
+    "-x", "-z", "-G", "-L", "-N", "-O", "-S",
+];
+
+/// Operators comparing two operands, except `=~`, which only `[[ ]]` has.
+pub const BINARY_OPERATORS: &[&str] = &[
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// `op operand`: file tests, string tests, `-t fd`, `-o option` and `-v variable`.
+pub fn unary(op: &str, operand: &str) -> Result<bool, String> {
+    let path = Path::new(operand);
+    let metadata = fs::metadata(path).ok();
+    let has_type = |is: fn(&fs::FileType) -> bool| metadata.as_ref().is_some_and(|m| is(&m.file_type()));
+    let has_mode = |bit: u32| metadata.as_ref().is_some_and(|m| m.mode() & bit != 0);
+    let (uid, gid) = sys::effective_ids();
+    Ok(match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-a" | "-e" => metadata.is_some(),
+        "-f" => has_type(fs::FileType::is_file),
+        "-d" => has_type(fs::FileType::is_dir),
+        "-b" => has_type(FileTypeExt::is_block_device),
+        "-c" => has_type(FileTypeExt::is_char_device),
+        "-p" => has_type(FileTypeExt::is_fifo),
+        "-S" => has_type(FileTypeExt::is_socket),
+        "-h" | "-L" => fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()),
+        "-s" => metadata.is_some_and(|m| m.len() > 0),
+        "-u" => has_mode(0o4000),
+        "-g" => has_mode(0o2000),
+        "-k" => has_mode(0o1000),
+        "-r" => sys::access(path, libc::R_OK),
+        "-w" => sys::access(path, libc::W_OK),
+        "-x" => sys::access(path, libc::X_OK),
+        "-O" => metadata.is_some_and(|m| m.uid() == uid),
+        "-G" => metadata.is_some_and(|m| m.gid() == gid),
+        "-N" => metadata.is_some_and(|m| (m.mtime(), m.mtime_nsec()) > (m.atime(), m.atime_nsec())),
+        "-t" => sys::is_terminal(integer(operand)? as i32),
+        "-o" => match operand {
+            "vi" => state::vi_mode(),
+            "emacs" => !state::vi_mode(),
+            "share_history" => state::share_history(),
+            _ => false,
+        },
+        "-v" => state::var(operand).is_some() || state::array(operand).is_some(),
+        _ => return Err(format!("{op}: unary operator expected")),
+    })
+}
+
+/// `left op right`, comparing strings, integers or files. String comparison is literal here;
+/// `[[ ]]` matches `==` and `!=` against a pattern before getting here.
+pub fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        // a file that exists is newer than one that doesn't
+        "-nt" => modified(left) > modified(right),
+        "-ot" => modified(right) > modified(left),
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+            _ => false,
+        },
+        "-a" => !left.is_empty() && !right.is_empty(),
+        "-o" => !left.is_empty() || !right.is_empty(),
+        _ => return Err(format!("{op}: binary operator expected")),
+    })
+}
+
+fn integer(s: &str) -> Result<i64, String> {
+    s.trim().parse().map_err(|_| format!("{s}: integer expression expected"))
+}
+
+/// Evaluates the arguments of `test` (and of `[` without its `]`). Up to four arguments follow the POSIX
+/// rules, which decide by the number of arguments whether e.g. `!` or `-n` is an operator or a string;
+/// longer expressions are parsed with `!`, `( )`, `-a` binding tighter than `-o`, and the primaries.
+pub fn test(args: &[String]) -> Result<bool, String> {
+    let is_binary = |op: &str| BINARY_OPERATORS.contains(&op) || op == "-a" || op == "-o";
+    match args.len() {
+        0 => Ok(false),
+        1 => Ok(!args[0].is_empty()),
+        2 if args[0] == "!" => Ok(args[1].is_empty()),
+        2 if UNARY_OPERATORS.contains(&&*args[0]) => unary(&args[0], &args[1]),
+        2 => Err(format!("{}: unary operator expected", args[0])),
+        3 if is_binary(&args[1]) => binary(&args[0], &args[1], &args[2]),
+        3 if args[0] == "!" => Ok(!test(&args[1..])?),
+        3 if args[0] == "(" && args[2] == ")" => Ok(!args[1].is_empty()),
+        4 if args[0] == "!" => Ok(!test(&args[1..])?),
+        4 if args[0] == "(" && args[3] == ")" => test(&args[1..3]),
+        _ => {
+            let mut parser = TestParser { args, pos: 0 };
+            let value = parser.or()?;
+            match args.get(parser.pos) {
+                None => Ok(value),
+                Some(extra) => Err(format!("{extra}: too many arguments")),
+            }
+        }
+    }
+}
+
+struct TestParser<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+impl<'a> TestParser<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).map(String::as_str)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut value = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            value = self.and()? || value;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut value = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            value = self.not()? && value;
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("!") && self.peek(1).is_some() {
+            self.pos += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let Some(first) = self.peek(0) else {
+            return Err("argument expected".to_string());
+        };
+        if let Some(op) = self.peek(1)
+            && BINARY_OPERATORS.contains(&op)
+            && let Some(right) = self.peek(2)
+        {
+            self.pos += 3;
+            return binary(first, op, right);
+        }
+        if first == "(" {
+            self.pos += 1;
+            let value = self.or()?;
+            if self.peek(0) != Some(")") {
+                return Err("`)' expected".to_string());
+            }
+            self.pos += 1;
+            return Ok(value);
+        }
+        if UNARY_OPERATORS.contains(&first)
+            && let Some(operand) = self.peek(1)
+        {
+            self.pos += 2;
+            return unary(first, operand);
+        }
+        self.pos += 1;
+        Ok(!first.is_empty())
+    }
+}
+
+/// Runs `[[ expr ]]`: status 0 if it is true, 1 if false, and 2 with a message on `stderr` if it can't be evaluated.
+pub fn run(expr: &ConditionalExpr, stderr: &mut dyn Write) -> i32 {
+    match evaluate(expr) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            _ = writeln!(stderr, "[[: {e}");
+            2
+        }
+    }
+}
+
+/// Evaluates a `[[ ]]` expression. `==` and `!=` match a glob pattern; `=~` matches a regex and sets
+/// `BASH_REMATCH` to the match and its groups (empty if it doesn't match). `&&` and `||` short-circuit.
+pub fn evaluate(expr: &ConditionalExpr) -> Result<bool, String> {
+    match expr {
+        ConditionalExpr::Not(e) => Ok(!evaluate(e)?),
+        ConditionalExpr::And(a, b) => Ok(evaluate(a)? && evaluate(b)?),
+        ConditionalExpr::Or(a, b) => Ok(evaluate(a)? || evaluate(b)?),
+        ConditionalExpr::Word(word) => Ok(!word.is_empty()),
+        ConditionalExpr::Unary(op, operand) => unary(op, operand),
+        ConditionalExpr::Binary(left, op, right) => match op.as_str() {
+            "==" | "=" => Ok(pattern::matches(right, left)),
+            "!=" => Ok(!pattern::matches(right, left)),
+            "=~" => {
+                let regex = Regex::new(right).map_err(|e| format!("{right}: {e}"))?;
+                let groups = regex.captures(left);
+                let matched = groups.is_some();
+                let groups = groups
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|g| g.map(|(start, end)| left[start..end].to_string()).unwrap_or_default())
+                    .collect();
+                // a shell array, so child processes never see it
+                state::set_array("BASH_REMATCH", groups).map_err(|e| format!("BASH_REMATCH: {e}"))?;
+                Ok(matched)
+            }
+            _ => binary(left, op, right),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_str(args: &str) -> Result<bool, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        test(&args)
+    }
+
+    #[test]
+    fn test_argument_counts() {
+        assert_eq!(test_str(""), Ok(false));
+        assert_eq!(test_str("-n"), Ok(true));
+        assert_eq!(test_str("! -z"), Ok(false));
+        assert_eq!(test_str("-z x"), Ok(false));
+        assert_eq!(test_str("! = !"), Ok(true));
+        assert_eq!(test_str("( -n )"), Ok(true));
+        assert_eq!(test_str("! a = b"), Ok(true));
+        assert!(test_str("x y").is_err());
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(test_str("10 -gt 9"), Ok(true));
+        assert_eq!(test_str("10 > 9"), Ok(false)); // strings
+        assert_eq!(test_str("-3 -le -3"), Ok(true));
+        assert!(test_str("a -eq 1").is_err());
+        assert_eq!(test_str("a = a -a ( b != c -o ! x )"), Ok(true));
+        assert_eq!(test_str("a = b -o a = a -a x = y"), Ok(false));
+        assert_eq!(test_str("-d / -a -f / -o -e /"), Ok(true));
+        assert_eq!(test_str("/ -ef /. -a / -nt /nonexistent"), Ok(true));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let word = |s: &str| s.to_string();
+        let binary = |l: &str, op: &str, r: &str| ConditionalExpr::Binary(word(l), word(op), word(r));
+        assert_eq!(evaluate(&binary("main.rs", "==", "*.rs")), Ok(true));
+        assert_eq!(evaluate(&binary("*.rs", "==", "\\*.rs")), Ok(true));
+        assert_eq!(evaluate(&binary("main.rs", "==", "\\*.rs")), Ok(false));
+        let both = ConditionalExpr::And(
+            Box::new(ConditionalExpr::Not(Box::new(ConditionalExpr::Word(String::new())))),
+            Box::new(binary("v1.22", "=~", "^v([0-9]+)\\.([0-9]+)$")),
+        );
+        assert_eq!(evaluate(&both), Ok(true));
+        assert_eq!(state::array("BASH_REMATCH").unwrap(), ["v1.22", "1", "22"]);
+        assert_eq!(std::env::var_os("BASH_REMATCH"), None);
+        assert!(evaluate(&binary("a", "=~", "(")).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! The working directory as the user sees it: the logical path kept in `$PWD`
+//! (symlinks as typed), `$OLDPWD`, `cd`'s `CDPATH` search, and the `pushd`/`popd` directory stack.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::jump;
+
+/// The directories saved by `pushd`, most recent first. The current directory is not in it:
+/// it is always the top of the stack `dirs` shows.
+static DIR_STACK: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// The logical working directory: `$PWD` when it still names the current directory, else the physical path.
+pub fn cwd() -> PathBuf {
+    let physical = env::current_dir().unwrap_or_default();
+    match env::var_os("PWD").map(PathBuf::from) {
+        Some(pwd) if pwd.is_absolute() && same_file(&pwd, Path::new(".")) => pwd,
+        _ => physical,
+    }
+}
+
+/// Exports a valid `$PWD` at startup, as bash does.
+pub fn init() {
+    let pwd = cwd();
+    unsafe { env::set_var("PWD", pwd); }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Removes `.` and `..` components without touching the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => { out.pop(); }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Changes to `target` and updates `$PWD` and `$OLDPWD`. A relative target is taken from the logical
+/// directory and `..` removes the last component of the path as typed (`cd -L`); with `physical` (`cd -P`)
+/// symlinks are resolved and `$PWD` becomes the physical path. The new directory is recorded for `j`.
+/// Returns the new `$PWD`.
+pub fn change_dir(target: &Path, physical: bool) -> io::Result<PathBuf> {
+    let old = cwd();
+    let new = if physical {
+        env::set_current_dir(target)?;
+        env::current_dir()?
+    } else {
+        let logical = normalize(&old.join(target));
+        match env::set_current_dir(&logical) {
+            Ok(()) => logical,
+            // `..` out of a symlinked directory that no longer makes sense lexically: fall back as bash does
+            Err(_) => {
+                env::set_current_dir(target)?;
+                env::current_dir()?
+            }
+        }
+    };
+    unsafe {
+        env::set_var("OLDPWD", &old);
+        env::set_var("PWD", &new);
+    }
+    jump::record(&new);
+    Ok(new)
+}
+
+/// Where `cd dir` goes: for a relative `dir` not starting with `.` or `..`, the first `$CDPATH` entry
+/// containing it. The flag is true when a non-empty `$CDPATH` entry was used, in which case `cd` prints the result.
+pub fn search_cdpath(dir: &str) -> (PathBuf, bool) {
+    let path = Path::new(dir);
+    let dotted = matches!(path.components().next(), Some(Component::CurDir | Component::ParentDir));
+    if path.is_absolute() || dotted {
+        return (path.to_path_buf(), false);
+    }
+    if let Ok(cdpath) = env::var("CDPATH") {
+        for entry in cdpath.split(':') {
+            let base = if entry.is_empty() { cwd() } else { cwd().join(entry) };
+            let candidate = base.join(path);
+            if candidate.is_dir() {
+                return (candidate, !entry.is_empty());
+            }
+        }
+    }
+    (path.to_path_buf(), false)
+}
+
+/// The whole stack as `dirs` shows it: the current directory, then the saved ones.
+pub fn stack() -> Vec<PathBuf> {
+    let mut stack = vec![cwd()];
+    stack.extend(DIR_STACK.lock().unwrap().iter().cloned());
+    stack
+}
+
+/// `pushd dir`: saves the current directory and changes to `dir`.
+pub fn push(dir: &Path) -> io::Result<()> {
+    let old = cwd();
+    change_dir(dir, false)?;
+    DIR_STACK.lock().unwrap().insert(0, old);
+    Ok(())
+}
+
+/// Makes entry `n` of `stack()` the top by rotating the stack, and changes to it (`pushd +N`).
+pub fn rotate(n: usize) -> io::Result<()> {
+    let mut stack = stack();
+    stack.rotate_left(n);
+    set_stack(stack)
+}
+
+/// Swaps the two top entries (`pushd` without arguments).
+pub fn swap() -> io::Result<()> {
+    let mut stack = stack();
+    stack.swap(0, 1);
+    set_stack(stack)
+}
+
+/// Removes entry `n` of `stack()`; removing the top changes to the next one (`popd`, `popd +N`).
+pub fn pop(n: usize) -> io::Result<()> {
+    let mut stack = stack();
+    stack.remove(n);
+    set_stack(stack)
+}
+
+/// `dirs -c`
+pub fn clear_stack() {
+    DIR_STACK.lock().unwrap().clear();
+}
+
+/// Changes to the new top of `stack` if needed and saves the rest. Leaves everything as it was if that fails.
+fn set_stack(mut stack: Vec<PathBuf>) -> io::Result<()> {
+    let top = stack.remove(0);
+    if top != cwd() {
+        change_dir(&top, false)?;
+    }
+    *DIR_STACK.lock().unwrap() = stack;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new("/a/b/../c/./d")), PathBuf::from("/a/c/d"));
+        assert_eq!(normalize(Path::new("/..")), PathBuf::from("/"));
+    }
+}
@@ -1,42 +1,171 @@
 use is_executable::IsExecutable;
+use std::collections::HashMap;
 use std::env;
+use std::ffi::OsString;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
 
+/// Executable names found in `$PATH`, rescanned when `PATH` or a directory's mtime changes.
+struct ExecCache {
+    path_var: Option<OsString>,
+    dir_mtimes: Vec<Option<SystemTime>>,
+    names: Arc<Vec<String>>,
+}
+
+/// `hash` table: command name -> (resolved path, hits). Cleared when `PATH` changes.
+struct HashTable {
+    path_var: Option<OsString>,
+    entries: HashMap<String, (PathBuf, usize)>,
+}
+
+static CACHE: LazyLock<Mutex<Option<ExecCache>>> = LazyLock::new(|| Mutex::new(None));
+static HASHED: LazyLock<Mutex<HashTable>> = LazyLock::new(|| {
+    Mutex::new(HashTable { path_var: None, entries: HashMap::new() })
+});
+
+fn path_dirs(path_var: &OsString) -> Vec<PathBuf> {
+    env::split_paths(path_var)
+        .map(|dir| {
+            // Empty PATH entries mean current directory
+            if dir.as_os_str().is_empty() { Path::new(".").to_path_buf() } else { dir }
+        })
+        .collect()
+}
+
+fn mtimes(dirs: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    dirs.iter()
+        .map(|dir| fs::metadata(dir).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Resolves `name` to an executable: its remembered location if hashed, else a `PATH` search.
+/// Remembers and counts nothing, so `type` and completion lookups leave the `hash` table alone;
+/// a command about to run is resolved with `find_executable_to_run`.
 pub fn find_executable_in_path(name: &str) -> Option<PathBuf> {
-    let path_var = env::var_os("PATH")?;
-    let paths = env::split_paths(&path_var);
+    resolve(name, false)
+}
 
-    for dir in paths {
-        // Empty PATH entries mean current directory
-        let dir = if dir.as_os_str().is_empty() {
-            Path::new(".").to_path_buf()
-        } else {
-            dir
-        };
+/// Resolves `name` for running it, remembering the result like bash's `hash` and counting a hit.
+pub fn find_executable_to_run(name: &str) -> Option<PathBuf> {
+    resolve(name, true)
+}
 
-        let candidate = dir.join(name);
+fn resolve(name: &str, run: bool) -> Option<PathBuf> {
+    let path_var = env::var_os("PATH");
+    let mut table = HASHED.lock().unwrap();
+    if table.path_var != path_var {
+        table.entries.clear();
+        table.path_var = path_var.clone();
+    }
 
-        if candidate.is_executable() {
-            return Some(candidate);
+    if let Some((path, hits)) = table.entries.get_mut(name) {
+        if path.is_executable() {
+            if run {
+                *hits += 1;
+            }
+            return Some(path.clone());
         }
+        table.entries.remove(name); // gone since it was hashed: search again
+    }
+
+    let found = search_path(name, &path_var?)?;
+    if run {
+        table.entries.insert(name.to_string(), (found.clone(), 1));
+    }
+    Some(found)
+}
+
+fn search_path(name: &str, path_var: &OsString) -> Option<PathBuf> {
+    path_dirs(path_var)
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_executable())
+}
+
+/// Every executable called `name` in `PATH`, in search order (`type -a`). Not hashed.
+pub fn find_all_in_path(name: &str) -> Vec<PathBuf> {
+    let Some(path_var) = env::var_os("PATH") else {
+        return Vec::new();
+    };
+    path_dirs(&path_var)
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .filter(|candidate| candidate.is_executable())
+        .collect()
+}
+
+/// `hash name`: looks `name` up in `PATH` and remembers it, without counting a hit.
+pub fn hash(name: &str) -> Option<PathBuf> {
+    let path = find_executable_in_path(name)?;
+    HASHED.lock().unwrap().entries.entry(name.to_string()).or_insert((path.clone(), 0));
+    Some(path)
+}
+
+/// `hash -p path name`
+pub fn hash_path(name: &str, path: PathBuf) {
+    HASHED.lock().unwrap().entries.insert(name.to_string(), (path, 0));
+}
+
+/// `hash -d name`; false if `name` was not hashed.
+pub fn unhash(name: &str) -> bool {
+    HASHED.lock().unwrap().entries.remove(name).is_some()
+}
+
+/// `hash -r`: forgets all remembered locations and rescans `PATH` on next use.
+pub fn rehash() {
+    HASHED.lock().unwrap().entries.clear();
+    *CACHE.lock().unwrap() = None;
+}
+
+/// The remembered path of `name`, if hashed.
+pub fn hashed(name: &str) -> Option<PathBuf> {
+    HASHED.lock().unwrap().entries.get(name).map(|(path, _)| path.clone())
+}
+
+/// The hash table as (name, path, hits), sorted by name.
+pub fn hash_table() -> Vec<(String, PathBuf, usize)> {
+    let mut entries: Vec<(String, PathBuf, usize)> = HASHED.lock().unwrap().entries
+        .iter()
+        .map(|(name, (path, hits))| (name.clone(), path.clone(), *hits))
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// All executable names in `$PATH`, sorted and deduplicated.
+/// Cached; rescanned when `PATH` or the modification time of one of its directories changes,
+/// so newly installed tools show up without restarting the shell. Until then every call shares one list.
+pub fn get_all_executables() -> Arc<Vec<String>> {
+    let path_var = env::var_os("PATH");
+    let dirs = path_var.as_ref().map(path_dirs).unwrap_or_default();
+    let dir_mtimes = mtimes(&dirs);
+
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(c) = cache.as_ref()
+        && c.path_var == path_var
+        && c.dir_mtimes == dir_mtimes
+    {
+        return Arc::clone(&c.names);
     }
 
-    None
+    let names = Arc::new(scan_executables(&dirs));
+    *cache = Some(ExecCache { path_var, dir_mtimes, names: Arc::clone(&names) });
+    names
 }
 
-pub fn get_all_executables() -> Vec<String> {
+fn scan_executables(dirs: &[PathBuf]) -> Vec<String> {
     let mut execs = Vec::new();
 
-    if let Some(path_var) = env::var_os("PATH") {
-        for path in env::split_paths(&path_var) {
-            if let Ok(entries) = std::fs::read_dir(path) {
-                for entry in entries.flatten() {
-                    let p = entry.path();
-                    if p.is_executable() {
-                        if let Some(name) = p.file_name().and_then(|n| n.to_str()) {
-                            execs.push(name.to_string());
-                        }
-                    }
+    for path in dirs {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                let p = entry.path();
+                if p.is_executable()
+                    && let Some(name) = p.file_name().and_then(|n| n.to_str())
+                {
+                    execs.push(name.to_string());
                 }
             }
         }
@@ -46,6 +175,10 @@ pub fn get_all_executables() -> Vec<String> {
     execs
 }
 
+/// Serializes the tests that change `PATH` or rely on the `hash` table, which are process-wide.
+#[cfg(test)]
+pub(crate) static ENV_LOCK: Mutex<()> = Mutex::new(());
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,6 +188,7 @@ mod tests {
 
     #[test]
     fn test_find_executable_with_single_quotes() {
+        let _env = ENV_LOCK.lock().unwrap();
         // Create a unique temporary directory
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -94,4 +228,73 @@ mod tests {
 
         assert_eq!(result, Some(exe_path));
     }
+
+    /// Makes an executable file `name` in `dir`.
+    fn make_executable(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        File::create(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_executables_cache_is_shared_until_path_changes() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let dir = std::env::temp_dir().join(format!("shell_exec_cache_test_{}", std::process::id()));
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        make_executable(&first, "cache-test-a");
+        make_executable(&second, "cache-test-b");
+        let original_path = env::var_os("PATH");
+        unsafe { env::set_var("PATH", &first); }
+
+        let before = get_all_executables();
+        assert_eq!(*before, ["cache-test-a"]);
+        assert!(Arc::ptr_eq(&before, &get_all_executables()));
+        unsafe { env::set_var("PATH", env::join_paths([&first, &second]).unwrap()); }
+        assert_eq!(*get_all_executables(), ["cache-test-a", "cache-test-b"]);
+
+        match original_path {
+            Some(path) => unsafe { env::set_var("PATH", path); },
+            None => unsafe { env::remove_var("PATH"); },
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_hash_table() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let dir = std::env::temp_dir().join(format!("shell_hash_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tool = make_executable(&dir, "hash-test-tool");
+        let original_path = env::var_os("PATH");
+        unsafe { env::set_var("PATH", &dir); }
+
+        // a lookup (`type`) neither remembers nor counts
+        assert_eq!(find_executable_in_path("hash-test-tool"), Some(tool.clone()));
+        assert_eq!(hashed("hash-test-tool"), None);
+        // `hash name` remembers without a hit, running counts one per run
+        assert_eq!(hash("hash-test-tool"), Some(tool.clone()));
+        assert_eq!(hash_table(), [("hash-test-tool".to_string(), tool.clone(), 0)]);
+        find_executable_to_run("hash-test-tool");
+        find_executable_to_run("hash-test-tool");
+        find_executable_in_path("hash-test-tool");
+        assert_eq!(hash_table(), [("hash-test-tool".to_string(), tool.clone(), 2)]);
+        // `hash -p` wins over PATH; `hash -d` forgets
+        hash_path("hash-test-tool", PathBuf::from("/bin/sh"));
+        assert_eq!(find_executable_to_run("hash-test-tool"), Some(PathBuf::from("/bin/sh")));
+        assert!(unhash("hash-test-tool"));
+        assert!(!unhash("hash-test-tool"));
+        // `hash -r` empties the table
+        find_executable_to_run("hash-test-tool");
+        rehash();
+        assert!(hash_table().is_empty());
+
+        match original_path {
+            Some(path) => unsafe { env::set_var("PATH", path); },
+            None => unsafe { env::remove_var("PATH"); },
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
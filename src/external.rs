@@ -1,7 +1,9 @@
-use std::io;
+use std::io::{self, Read, Write};
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
 
 /// Prepares a Command object with the executable path and arguments.
 /// Handles argv[0] setting.
@@ -16,3 +18,47 @@ pub fn run_unix(path: PathBuf, name: &str, args: &[String]) -> io::Result<i32> {
     let status = prepare_unix_command(&path, name, args).status()?;
     Ok(status.code().unwrap_or(128))
 }
+
+/// Runs an external command with its stdout and stderr copied into the given writers as it produces them,
+/// for a builtin that runs commands itself (`command name`) and so must honor its redirections and pipes.
+/// Stdin is inherited.
+pub fn run_into(path: &Path, name: &str, args: &[String], stdout: &mut dyn Write, stderr: &mut dyn Write) -> io::Result<i32> {
+    let mut child = prepare_unix_command(path, name, args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // One thread per pipe, so a child filling one of them while we wait on the other cannot hang.
+    let (tx, rx) = mpsc::channel();
+    let pumps = [
+        pump(child.stdout.take().unwrap(), false, tx.clone()),
+        pump(child.stderr.take().unwrap(), true, tx),
+    ];
+    // On a write error the receiver is dropped here, which stops the pumps and closes the pipes.
+    let copied = rx.into_iter().try_for_each(|(is_err, chunk)| {
+        if is_err { stderr.write_all(&chunk) } else { stdout.write_all(&chunk) }
+    });
+    for pump in pumps {
+        _ = pump.join();
+    }
+    let status = child.wait()?;
+    copied?;
+    Ok(status.code().unwrap_or(128))
+}
+
+/// Sends what `pipe` delivers to `tx` until end of file, tagged with `is_err`.
+fn pump(mut pipe: impl Read + Send + 'static, is_err: bool, tx: Sender<(bool, Vec<u8>)>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if tx.send((is_err, buf[..n].to_vec())).is_err() {
+                break;
+            }
+        }
+    })
+}
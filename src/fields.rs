@@ -0,0 +1,57 @@
+//! Splitting a line into fields on the characters of `$IFS`, as `read` does.
+
+/// `$IFS` when it is not set: space, tab and newline.
+pub const DEFAULT_IFS: &str = " \t\n";
+
+/// Splits `chars` into at most `max` fields on the characters of `ifs`; the last field gets the rest of the line.
+/// Each char comes with a flag telling whether it was escaped (by a backslash), which keeps it from separating.
+/// Runs of IFS whitespace count as one separator and are trimmed from both ends; every other IFS character
+/// ends a field on its own, so `a::b` with `IFS=:` has an empty middle field.
+pub fn split(chars: &[(char, bool)], ifs: &str, max: usize) -> Vec<String> {
+    let is_white = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c) && c.is_whitespace();
+    let is_sep = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+
+    let start = chars.iter().take_while(|c| is_white(c)).count();
+    let end = chars.len() - chars[start..].iter().rev().take_while(|c| is_white(c)).count();
+    let chars = &chars[start..end];
+
+    let mut fields = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if fields.len() + 1 == max {
+            fields.push(chars[i..].iter().map(|&(c, _)| c).collect());
+            return fields;
+        }
+        let len = chars[i..].iter().take_while(|c| !is_sep(c)).count();
+        fields.push(chars[i..i + len].iter().map(|&(c, _)| c).collect());
+        i += len;
+        // the separator: surrounding whitespace plus at most one other IFS character
+        i += chars[i..].iter().take_while(|c| is_white(c)).count();
+        if chars.get(i).is_some_and(|c| is_sep(c) && !is_white(c)) {
+            i += 1;
+            i += chars[i..].iter().take_while(|c| is_white(c)).count();
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_str(s: &str, ifs: &str, max: usize) -> Vec<String> {
+        let chars: Vec<(char, bool)> = s.chars().map(|c| (c, false)).collect();
+        split(&chars, ifs, max)
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split_str("  a  b\tc  ", DEFAULT_IFS, usize::MAX), ["a", "b", "c"]);
+        assert_eq!(split_str("  a  b   c  ", DEFAULT_IFS, 2), ["a", "b   c"]);
+        assert_eq!(split_str("a::b : c", ":", usize::MAX), ["a", "", "b ", " c"]);
+        assert_eq!(split_str("a : b", " :", usize::MAX), ["a", "b"]);
+        assert_eq!(split_str("", DEFAULT_IFS, 1), Vec::<String>::new());
+        let escaped = [('a', false), (' ', true), ('b', false), (' ', false), ('c', false)];
+        assert_eq!(split(&escaped, DEFAULT_IFS, usize::MAX), ["a b", "c"]);
+    }
+}
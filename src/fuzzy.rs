@@ -0,0 +1,318 @@
+//! The Ctrl-R fuzzy history finder: ranks history entries against a typed query
+//! and lets the user pick one from a list drawn below the input line.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::histdb::Record;
+use crate::{dirs, history, prompt, sys};
+
+/// Candidates shown at once.
+const MAX_SHOWN: usize = 10;
+const MATCH: &str = "\x1b[1;33m"; // bold yellow
+const SELECTED: &str = "\x1b[7m"; // reverse video
+const DIM: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// Characters after which a match counts as the start of a word.
+const WORD_SEPARATORS: &str = " /-_.|;:=,'\"";
+
+/// Scores `text` against `query` if all query characters appear in it in order, and returns the matched
+/// character positions. Consecutive matches, matches at word starts and whole-substring matches score higher,
+/// gaps lower. Case-insensitive unless the query has an uppercase letter.
+pub fn score(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let fold = |c: char| if case_sensitive { c } else { c.to_ascii_lowercase() };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let text: Vec<char> = text.chars().collect();
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    // try every occurrence of the first query char as the start of a greedy match; keep the best
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in (0..text.len()).filter(|&i| fold(text[i]) == query[0]) {
+        let mut positions = vec![start];
+        let mut i = start + 1;
+        for &q in &query[1..] {
+            match (i..text.len()).find(|&j| fold(text[j]) == q) {
+                Some(j) => {
+                    positions.push(j);
+                    i = j + 1;
+                }
+                None => break,
+            }
+        }
+        if positions.len() < query.len() {
+            break; // later starts can't match either
+        }
+        let mut total = -(start.min(10) as i64);
+        for (k, &p) in positions.iter().enumerate() {
+            total += 16;
+            if p == 0 || WORD_SEPARATORS.contains(text[p - 1]) {
+                total += 8;
+            }
+            if k > 0 {
+                let gap = p - positions[k - 1] - 1;
+                total += if gap == 0 { 12 } else { -(gap.min(10) as i64) };
+            }
+        }
+        if best.as_ref().is_none_or(|(b, _)| total > *b) {
+            best = Some((total, positions));
+        }
+    }
+    best
+}
+
+/// Ranks `records` (oldest first) for `query`: match score, minus a penalty growing with age.
+/// Each command appears once, as its most recent entry; with `cwd` only entries run there are kept.
+/// Returns indices into `records` with the matched positions, best first.
+pub fn rank(query: &str, records: &[Record], cwd: Option<&str>) -> Vec<(usize, Vec<usize>)> {
+    let mut seen = HashSet::new();
+    let mut ranked: Vec<(i64, usize, Vec<usize>)> = Vec::new();
+    for (age, (i, record)) in records.iter().enumerate().rev().enumerate() {
+        if cwd.is_some() && record.cwd.as_deref() != cwd {
+            continue;
+        }
+        if !seen.insert(record.command.as_str()) {
+            continue;
+        }
+        if let Some((score, positions)) = score(query, &record.command) {
+            let recency = 2 * (age as i64 + 1).ilog2() as i64;
+            ranked.push((score - recency, i, positions));
+        }
+    }
+    // stable: equal scores stay newest first
+    ranked.sort_by_key(|&(score, _, _)| std::cmp::Reverse(score));
+    ranked.into_iter().map(|(_, i, positions)| (i, positions)).collect()
+}
+
+/// Runs the finder on the terminal, starting with `query`, while the line editor is in raw mode.
+/// Returns the chosen command, or `None` if the user cancelled.
+pub fn run(query: &str) -> Option<String> {
+    let (cols, rows) = sys::terminal_size().unwrap_or((80, 24));
+    let mut finder = Finder {
+        query: query.to_string(),
+        records: history::records(),
+        cwd: dirs::cwd().display().to_string(),
+        only_cwd: false,
+        selected: 0,
+        matches: Vec::new(),
+        shown: MAX_SHOWN.min(rows.saturating_sub(3)).max(1),
+        cols: cols.max(10),
+    };
+    finder.update();
+
+    let height = finder.shown + 2;
+    let mut out = io::stdout();
+    // make room below the input line first, so drawing never scrolls the screen under us
+    _ = write!(out, "{}\x1b[{}A", "\n".repeat(height), height);
+    let chosen = loop {
+        finder.draw(&mut out);
+        match read_key()? {
+            Key::Accept => break finder.matches.get(finder.selected).map(|(i, _)| finder.records[*i].command.clone()),
+            Key::Cancel => break None,
+            Key::Next => finder.selected = (finder.selected + 1).min(finder.matches.len().saturating_sub(1)),
+            Key::Previous => finder.selected = finder.selected.saturating_sub(1),
+            Key::ToggleCwd => {
+                finder.only_cwd = !finder.only_cwd;
+                finder.update();
+            }
+            Key::Backspace => {
+                finder.query.pop();
+                finder.update();
+            }
+            Key::Clear => {
+                finder.query.clear();
+                finder.update();
+            }
+            Key::Char(c) => {
+                finder.query.push(c);
+                finder.update();
+            }
+            Key::Ignore => {}
+        }
+    };
+    // wipe the list; the editor repaints the input line
+    _ = write!(out, "\r\n\x1b[J\x1b[1A");
+    _ = out.flush();
+    chosen
+}
+
+struct Finder {
+    query: String,
+    /// Oldest first, as `history::records` returns them.
+    records: Vec<Record>,
+    cwd: String,
+    /// Only show commands run in `cwd` (toggled with Ctrl-T).
+    only_cwd: bool,
+    selected: usize,
+    matches: Vec<(usize, Vec<usize>)>,
+    shown: usize,
+    cols: usize,
+}
+
+impl Finder {
+    fn update(&mut self) {
+        let cwd = Some(self.cwd.as_str()).filter(|_| self.only_cwd);
+        self.matches = rank(&self.query, &self.records, cwd);
+        self.selected = 0;
+    }
+
+    /// Draws the query line, the candidates and a preview of the selected entry below the cursor's row,
+    /// then moves back up to that row.
+    fn draw(&self, out: &mut dyn Write) {
+        let mut s = String::new();
+        let scope = if self.only_cwd { " (this directory)" } else { "" };
+        s.push_str(&format!(
+            "\r\n\x1b[K{DIM}{}/{}{}{RESET} > {}",
+            self.matches.len(),
+            self.records.len(),
+            scope,
+            self.query
+        ));
+        let first = self.selected.saturating_sub(self.shown - 1);
+        for row in 0..self.shown {
+            s.push_str("\r\n\x1b[K");
+            let Some((i, positions)) = self.matches.get(first + row) else {
+                continue;
+            };
+            let selected = first + row == self.selected;
+            let base = if selected { SELECTED } else { "" };
+            s.push_str(base);
+            s.push_str(if selected { "> " } else { "  " });
+            for (k, c) in self.records[*i].command.chars().take(self.cols - 3).enumerate() {
+                let c = if c == '\n' { '↵' } else { c };
+                if positions.contains(&k) {
+                    s.push_str(&format!("{MATCH}{c}{RESET}{base}"));
+                } else {
+                    s.push(c);
+                }
+            }
+            s.push_str(RESET);
+        }
+        s.push_str("\r\n\x1b[K");
+        if let Some((i, _)) = self.matches.get(self.selected) {
+            let preview: String = preview(&self.records[*i]).chars().take(self.cols - 1).collect();
+            s.push_str(&format!("{DIM}{preview}{RESET}"));
+        }
+        s.push_str(&format!("\x1b[{}A\r", self.shown + 2));
+        _ = out.write_all(s.as_bytes());
+        _ = out.flush();
+    }
+}
+
+/// When, where and how the entry ran, as far as known.
+fn preview(record: &Record) -> String {
+    let mut parts = Vec::new();
+    if let Some(start) = record.start {
+        parts.push(sys::strftime("%F %T", start));
+    }
+    if let Some(status) = record.status {
+        parts.push(format!("exit {status}"));
+    }
+    if let Some(ms) = record.duration_ms {
+        parts.push(format!("{}.{:03}s", ms / 1000, ms % 1000));
+    }
+    if let Some(cwd) = &record.cwd {
+        parts.push(prompt::abbreviate_home(cwd));
+    }
+    parts.join("  ")
+}
+
+enum Key {
+    Accept,
+    Cancel,
+    Next,
+    Previous,
+    ToggleCwd,
+    Backspace,
+    Clear,
+    Char(char),
+    Ignore,
+}
+
+/// Reads and decodes one key press; `None` at end of input.
+fn read_key() -> Option<Key> {
+    let key = match sys::read_byte(-1)? {
+        b'\r' | b'\n' => Key::Accept,
+        0x03 | 0x07 => Key::Cancel,                // Ctrl-C, Ctrl-G
+        0x12 | 0x0e => Key::Next,                  // Ctrl-R, Ctrl-N
+        0x10 | 0x13 => Key::Previous,              // Ctrl-P, Ctrl-S
+        0x14 => Key::ToggleCwd,                    // Ctrl-T
+        0x7f | 0x08 => Key::Backspace,
+        0x15 => Key::Clear,                        // Ctrl-U
+        0x1b => match sys::read_byte(50) {
+            None => Key::Cancel, // a lone Esc
+            Some(b'[' | b'O') => {
+                // CSI/SS3 sequence: parameters, then a final byte
+                let mut last = 0;
+                while let Some(b) = sys::read_byte(50) {
+                    last = b;
+                    if (0x40..=0x7e).contains(&b) {
+                        break;
+                    }
+                }
+                match last {
+                    b'A' => Key::Previous,
+                    b'B' => Key::Next,
+                    _ => Key::Ignore,
+                }
+            }
+            Some(_) => Key::Ignore,
+        },
+        b if b >= 0x80 => {
+            // UTF-8: the lead byte gives the length
+            let len = match b {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                _ => 2,
+            };
+            let mut bytes = vec![b];
+            for _ in 1..len {
+                bytes.push(sys::read_byte(50)?);
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Ignore,
+            }
+        }
+        b if b >= 0x20 => Key::Char(b as char),
+        _ => Key::Ignore,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(command: &str, cwd: &str) -> Record {
+        Record { command: command.to_string(), cwd: Some(cwd.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score("gst", "git status").map(|(_, p)| p), Some(vec![0, 4, 5]));
+        assert!(score("xyz", "git status").is_none());
+        // consecutive and word-start matches beat scattered ones
+        assert!(score("stat", "git status").unwrap().0 > score("stat", "sxtxaxt").unwrap().0);
+        // smart case
+        assert!(score("Git", "git").is_none());
+        assert!(score("git", "GIT").is_some());
+    }
+
+    #[test]
+    fn test_rank_dedupes_and_prefers_recent() {
+        let records = [
+            record("make test", "/a"),
+            record("cargo build", "/b"),
+            record("make test", "/b"),
+            record("cargo test", "/a"),
+        ];
+        let ranked: Vec<usize> = rank("", &records, None).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(ranked, [3, 2, 1]);
+        let ranked: Vec<usize> = rank("test", &records, Some("/a")).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(ranked, [3, 0]);
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use is_executable::IsExecutable;
+
+use crate::builtins;
+use crate::parse::{self, TokenKind};
+
+const COMMAND: &str = "\x1b[1;32m"; // bold green
+const UNKNOWN_COMMAND: &str = "\x1b[1;31m"; // bold red
+const QUOTED: &str = "\x1b[33m"; // yellow
+const VARIABLE: &str = "\x1b[36m"; // cyan: `$NAME`, `${NAME}`, `$?`
+const OPERATOR: &str = "\x1b[35m"; // magenta: | || && and redirections
+const PATH: &str = "\x1b[4m"; // underline
+const RESET: &str = "\x1b[0m";
+
+/// Adds ANSI colours to a command line. Works on the parser's token spans,
+/// so a word is coloured as a command exactly when the parser would run it as one.
+/// Only escape sequences are inserted: the visible text (and width) is unchanged.
+/// `executables` are the names in `PATH`, sorted.
+pub fn highlight(line: &str, executables: &[String]) -> String {
+    let lexed = parse::lex(line);
+    let builtins = builtins::all();
+
+    let mut out = String::with_capacity(line.len() * 2);
+    let mut copied = 0; // bytes of `line` already in `out`
+    let mut expect_command = true;
+    let mut in_conditional = false; // `&&` and `||` inside `[[ ]]` don't start a command
+
+    for token in &lexed.tokens {
+        out.push_str(&line[copied..token.start]); // blanks between tokens
+        let raw = &line[token.start..token.end];
+        copied = token.end;
+
+        if token.kind == TokenKind::Operator {
+            push_styled(&mut out, OPERATOR, raw);
+            expect_command = !in_conditional;
+            continue;
+        }
+        if (expect_command && raw == "[[") || (in_conditional && raw == "]]") {
+            push_styled(&mut out, COMMAND, raw);
+            in_conditional = !in_conditional;
+            expect_command = false;
+            continue;
+        }
+        // as `parse_simple` decides, on the word without its quotes
+        if !in_conditional && parse::redirect_kind(&token.text).is_some() {
+            push_styled(&mut out, OPERATOR, raw);
+            continue;
+        }
+
+        let style = if expect_command {
+            expect_command = false;
+            let known = if token.text.contains('/') {
+                Path::new(&token.text).is_executable()
+            } else {
+                builtins.contains(&token.text.as_str()) || executables.binary_search(&token.text).is_ok()
+            };
+            if known { COMMAND } else { UNKNOWN_COMMAND }
+        } else if !token.text.is_empty() && Path::new(&token.text).exists() {
+            PATH
+        } else {
+            ""
+        };
+        push_word(&mut out, style, raw);
+    }
+    out.push_str(&line[copied..]);
+    out
+}
+
+fn push_styled(out: &mut String, style: &str, s: &str) {
+    out.push_str(style);
+    out.push_str(s);
+    out.push_str(RESET);
+}
+
+/// Writes a word in `style`, with its quoted parts (quotes included) in `QUOTED`
+/// and variable references outside single quotes in `VARIABLE`.
+fn push_word(out: &mut String, style: &str, raw: &str) {
+    out.push_str(style);
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut chars = raw.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('\''), '\'') | (Some('"'), '"') => {
+                out.push(c);
+                out.push_str(RESET);
+                out.push_str(style);
+                quote = None;
+                continue;
+            }
+            (None, '\'' | '"') => {
+                out.push_str(QUOTED);
+                quote = Some(c);
+            }
+            (None | Some('"'), '\\') => escaped = true,
+            (None | Some('"'), '$') if variable_len(&raw[i + 1..]) > 0 => {
+                let end = i + 1 + variable_len(&raw[i + 1..]);
+                push_styled(out, VARIABLE, &raw[i..end]);
+                out.push_str(if quote.is_some() { QUOTED } else { style });
+                while chars.clone().next().is_some_and(|(j, _)| j < end) {
+                    chars.next();
+                }
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    out.push_str(RESET);
+}
+
+/// The length of the variable name after a `$`: `NAME`, `{...}`, or one special character (`$?`, `$1`, ...).
+/// 0 if there is none, e.g. for a lone `$`.
+fn variable_len(s: &str) -> usize {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some('{') => s.find('}').map_or(0, |end| end + 1),
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            1 + chars.take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count()
+        }
+        Some(c) if c.is_ascii_digit() || "?$#!@*-".contains(c) => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_ansi(s: &str) -> String {
+        let mut out = String::new();
+        let mut in_escape = false;
+        for c in s.chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                _ if in_escape => {}
+                _ => out.push(c),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_visible_text_is_unchanged() {
+        let line = "echo 'a b' \"c$X\"d $ ${Y}z |  nosuchcmd >> out.txt && ls\\ x$1";
+        assert_eq!(strip_ansi(&highlight(line, &[])), line);
+    }
+
+    #[test]
+    fn test_command_colours() {
+        assert!(highlight("echo hi", &[]).starts_with(&format!("{COMMAND}echo{RESET}")));
+        assert!(highlight("nosuchcmd-xyz", &[]).starts_with(&format!("{UNKNOWN_COMMAND}nosuchcmd-xyz")));
+        assert!(highlight("tool-xyz", &["tool-xyz".to_string()]).starts_with(&format!("{COMMAND}tool-xyz")));
+        assert!(highlight("a | echo", &[]).contains(&format!("{OPERATOR}|{RESET} {COMMAND}echo")));
+        let conditional = highlight("[[ a && nosuchcmd-xyz ]] && echo", &[]);
+        assert!(!conditional.contains(UNKNOWN_COMMAND));
+        assert!(conditional.ends_with(&format!("{COMMAND}echo{RESET}")));
+    }
+
+    #[test]
+    fn test_variables() {
+        let out = highlight("echo $HOME/x \"a $? ${B}\" '$no' \\$no $", &[]);
+        assert!(out.contains(&format!("{VARIABLE}$HOME{RESET}/x")));
+        assert!(out.contains(&format!("{QUOTED}\"a {VARIABLE}$?{RESET}{QUOTED} {VARIABLE}${{B}}{RESET}{QUOTED}\"")));
+        assert_eq!(out.matches(VARIABLE).count(), 3);
+    }
+
+    #[test]
+    fn test_redirects_as_the_parser_sees_them() {
+        assert!(highlight("echo hi 2>> log", &[]).contains(&format!("{OPERATOR}2>>{RESET}")));
+        assert!(highlight("echo hi '>' log", &[]).contains(&format!("{OPERATOR}'>'{RESET}")));
+        // not a redirect for `parse` either: operators must be separate words
+        assert!(!highlight("echo a>b", &[]).contains(OPERATOR));
+    }
+
+    #[test]
+    fn test_quoted_part() {
+        let out = highlight("echo 'hi there'", &[]);
+        assert!(out.contains(&format!("{QUOTED}'hi there'{RESET}")));
+    }
+}
@@ -0,0 +1,280 @@
+//! Optional structured history. When `$HISTDB` names a file, every command is also appended to it
+//! as one JSON object per line, recording where, when and how it ran; `history search` queries it.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::{history, sys};
+
+/// Identifies this shell process in the records: start time and pid.
+static SESSION_ID: LazyLock<String> = LazyLock::new(|| format!("{}-{}", sys::now(), std::process::id()));
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    pub command: String,
+    pub cwd: Option<String>,
+    pub status: Option<i32>,
+    /// Seconds since the epoch.
+    pub start: Option<i64>,
+    pub duration_ms: Option<u64>,
+    pub host: String,
+    pub session: String,
+}
+
+impl Record {
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![format!("\"command\":{}", json_string(&self.command))];
+        if let Some(cwd) = &self.cwd {
+            fields.push(format!("\"cwd\":{}", json_string(cwd)));
+        }
+        if let Some(status) = self.status {
+            fields.push(format!("\"status\":{status}"));
+        }
+        if let Some(start) = self.start {
+            fields.push(format!("\"start\":{start}"));
+        }
+        if let Some(duration) = self.duration_ms {
+            fields.push(format!("\"duration_ms\":{duration}"));
+        }
+        fields.push(format!("\"host\":{}", json_string(&self.host)));
+        fields.push(format!("\"session\":{}", json_string(&self.session)));
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// Parses a line written by `to_json`. Unknown keys are skipped; `None` if the line isn't a JSON object.
+    pub fn from_json(line: &str) -> Option<Record> {
+        let mut parser = JsonParser { s: line.trim(), pos: 0 };
+        let mut record = Record::default();
+        parser.expect('{')?;
+        if parser.eat('}') {
+            return Some(record);
+        }
+        loop {
+            let key = parser.string()?;
+            parser.expect(':')?;
+            let value = parser.value()?;
+            match (key.as_str(), value) {
+                ("command", Value::String(s)) => record.command = s,
+                ("cwd", Value::String(s)) => record.cwd = Some(s),
+                ("status", Value::Number(n)) => record.status = i32::try_from(n).ok(),
+                ("start", Value::Number(n)) => record.start = Some(n),
+                ("duration_ms", Value::Number(n)) => record.duration_ms = u64::try_from(n).ok(),
+                ("host", Value::String(s)) => record.host = s,
+                ("session", Value::String(s)) => record.session = s,
+                _ => {}
+            }
+            if parser.eat('}') {
+                return Some(record);
+            }
+            parser.expect(',')?;
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+enum Value {
+    String(String),
+    Number(i64),
+    Other,
+}
+
+/// Just enough JSON for the flat objects `to_json` writes.
+struct JsonParser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_blanks(&mut self) {
+        self.pos += self.s[self.pos..].len() - self.s[self.pos..].trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_blanks();
+        let found = self.s[self.pos..].starts_with(c);
+        if found {
+            self.pos += c.len_utf8();
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.eat(c).then_some(())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.s[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Some(out);
+                }
+                '\\' => match chars.next()?.1 {
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'b' => out.push('\x08'),
+                    'f' => out.push('\x0c'),
+                    'u' => {
+                        let hex: String = (0..4).filter_map(|_| chars.next().map(|(_, h)| h)).collect();
+                        out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?).unwrap_or('\u{fffd}'));
+                    }
+                    other => out.push(other),
+                },
+                c => out.push(c),
+            }
+        }
+        None
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_blanks();
+        if self.s[self.pos..].starts_with('"') {
+            return self.string().map(Value::String);
+        }
+        let len = self.s[self.pos..].find([',', '}']).unwrap_or(self.s.len() - self.pos);
+        let raw = self.s[self.pos..self.pos + len].trim();
+        self.pos += len;
+        Some(raw.parse().map(Value::Number).unwrap_or(Value::Other))
+    }
+}
+
+/// The database file, if `$HISTDB` is set.
+pub fn path() -> Option<PathBuf> {
+    env::var_os("HISTDB").filter(|p| !p.is_empty()).map(PathBuf::from)
+}
+
+pub fn session_id() -> &'static str {
+    &SESSION_ID
+}
+
+/// Appends one record as a single write, under the same lock as the history file.
+pub fn append(path: &Path, record: &Record) -> io::Result<()> {
+    let _lock = history::lock_file(path)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{}\n", record.to_json()).as_bytes())
+}
+
+/// All records in the database, oldest first. Lines that don't parse are skipped.
+pub fn read_all(path: &Path) -> io::Result<Vec<Record>> {
+    let content = {
+        let _lock = history::lock_file(path)?;
+        fs::read_to_string(path)?
+    };
+    Ok(content.lines().filter_map(Record::from_json).collect())
+}
+
+/// `history search` criteria; a record must match all that are set.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Run in this directory (or below it, with `recursive`).
+    pub dir: Option<String>,
+    pub recursive: bool,
+    pub status: Option<i32>,
+    /// Only commands that failed (non-zero status).
+    pub failed: bool,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub text: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record) -> bool {
+        if let Some(dir) = &self.dir {
+            let Some(cwd) = &record.cwd else { return false };
+            let below = self.recursive
+                && cwd.strip_prefix(dir.as_str()).is_some_and(|rest| rest.starts_with('/') || dir.ends_with('/'));
+            if cwd != dir && !below {
+                return false;
+            }
+        }
+        (self.status.is_none() || record.status == self.status)
+            && (!self.failed || record.status.is_some_and(|s| s != 0))
+            && self.since.is_none_or(|since| record.start.is_some_and(|t| t >= since))
+            && self.until.is_none_or(|until| record.start.is_some_and(|t| t <= until))
+            && self.text.as_ref().is_none_or(|text| record.command.contains(text.as_str()))
+    }
+}
+
+/// Parses a `history search` time: epoch seconds, or an age like `30s`, `15m`, `2h`, `3d`, `1w`.
+/// None if it is malformed or too far back to represent.
+pub fn parse_time(s: &str, now: i64) -> Option<i64> {
+    let unit = match s.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return s.parse().ok(),
+    };
+    let n: i64 = s[..s.len() - 1].parse().ok()?;
+    now.checked_sub(n.checked_mul(unit)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let record = Record {
+            command: "echo \"hi\"\n\tdone \\ é".to_string(),
+            cwd: Some("/tmp".to_string()),
+            status: Some(1),
+            start: Some(1700000000),
+            duration_ms: Some(42),
+            host: "box".to_string(),
+            session: "1-2".to_string(),
+        };
+        assert_eq!(Record::from_json(&record.to_json()), Some(record));
+        assert_eq!(Record::from_json("not json"), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let record = Record {
+            command: "cargo build".to_string(),
+            cwd: Some("/src/app".to_string()),
+            status: Some(101),
+            start: Some(1000),
+            ..Default::default()
+        };
+        assert!(Filter { dir: Some("/src".to_string()), recursive: true, failed: true, ..Default::default() }.matches(&record));
+        assert!(!Filter { dir: Some("/src".to_string()), ..Default::default() }.matches(&record));
+        assert!(!Filter { dir: Some("/sr".to_string()), recursive: true, ..Default::default() }.matches(&record));
+        assert!(Filter { since: Some(1000), until: Some(2000), text: Some("build".to_string()), ..Default::default() }.matches(&record));
+        assert!(!Filter { status: Some(0), ..Default::default() }.matches(&record));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1700000000", 0), Some(1700000000));
+        assert_eq!(parse_time("2h", 10000), Some(2800));
+        assert_eq!(parse_time("xh", 0), None);
+        assert_eq!(parse_time("9223372036854775807w", 0), None);
+        assert_eq!(parse_time("9223372036854775807s", -2), None);
+    }
+}
@@ -0,0 +1,390 @@
+//! Bash-style history expansion (`!!`, `!$`, `!n`, `!-n`, `!prefix`, `!?sub?`, `^old^new^`),
+//! with word designators and the `:h :t :r :e :p :q :x :s :gs :& :g&` modifiers.
+
+use std::sync::Mutex;
+
+use crate::parse;
+
+/// `(old, new)` of the last `:s`, reused by `:&` and by `:s` with an empty pattern.
+static LAST_SUBST: Mutex<Option<(String, String)>> = Mutex::new(None);
+
+/// Characters that end a `!prefix` event.
+const EVENT_DELIMITERS: &str = " \t\n;&()|<>:\"'";
+
+#[derive(Debug, PartialEq)]
+pub struct Expansion {
+    pub line: String,
+    /// At least one `!` or `^` was expanded; bash echoes the line in that case.
+    pub expanded: bool,
+    /// A `:p` modifier was used: print the line, don't run it.
+    pub print_only: bool,
+}
+
+/// Expands history references in `line`. `history` is oldest-first, so `!n` is `history[n - 1]`.
+/// Nothing is expanded inside single quotes or after a backslash.
+pub fn expand(line: &str, history: &[String]) -> Result<Expansion, String> {
+    let quick_subst;
+    let line = if line.starts_with('^') {
+        // `^old^new^` is short for `!!:s^old^new^`
+        quick_subst = format!("!!:s{line}");
+        quick_subst.as_str()
+    } else {
+        line
+    };
+
+    let mut result = Expansion { line: String::with_capacity(line.len()), expanded: false, print_only: false };
+    let mut in_single_quote = false;
+    let mut in_double_quote = false;
+    let mut i = 0;
+
+    while let Some(c) = line[i..].chars().next() {
+        let next = line[i + c.len_utf8()..].chars().next();
+        match c {
+            '\'' if in_single_quote => in_single_quote = false,
+            _ if in_single_quote => {}
+            '\'' if !in_double_quote => in_single_quote = true,
+            '"' => in_double_quote = !in_double_quote,
+            '\\' => {
+                if let Some(n) = next {
+                    result.line.push(c);
+                    result.line.push(n);
+                    i += c.len_utf8() + n.len_utf8();
+                    continue;
+                }
+            }
+            // `!` before a blank, `=`, `(` or a closing double quote stays literal
+            '!' if !(matches!(next, None | Some(' ' | '\t' | '\n' | '=' | '('))
+                || in_double_quote && next == Some('"')) =>
+            {
+                let mut reference = Reference { line, pos: i + 1, history, print_only: false };
+                let text = reference.expand()?;
+                result.line.push_str(&text);
+                result.expanded = true;
+                result.print_only |= reference.print_only;
+                i = reference.pos;
+                continue;
+            }
+            _ => {}
+        }
+        result.line.push(c);
+        i += c.len_utf8();
+    }
+    Ok(result)
+}
+
+/// One `!...` reference being parsed; `pos` is just past what has been consumed.
+struct Reference<'a> {
+    line: &'a str,
+    pos: usize,
+    history: &'a [String],
+    print_only: bool,
+}
+
+impl Reference<'_> {
+    fn peek(&self) -> Option<char> {
+        self.line[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += c.len_utf8();
+        }
+        found
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let digits = self.line[self.pos..].chars().take_while(char::is_ascii_digit).count();
+        let n = self.line[self.pos..self.pos + digits].parse().ok()?;
+        self.pos += digits;
+        Some(n)
+    }
+
+    fn expand(&mut self) -> Result<String, String> {
+        let start = self.pos - 1;
+        let (event, search) = self.event(start)?;
+        let words: Vec<&str> = {
+            let lexed = parse::lex(&event);
+            lexed.tokens.iter().map(|t| &event[t.start..t.end]).collect()
+        };
+        // `%` is the word that matched a `!?sub?` search
+        let matched = search.and_then(|s| words.iter().position(|w| w.contains(&s)));
+
+        // the `:` before a designator may be left out when it starts with `^ $ * %`
+        let designator = if self.eat(':') {
+            let found = self.peek().is_some_and(|c| c.is_ascii_digit() || "^$*%-".contains(c));
+            if !found {
+                self.pos -= 1; // `:` starts a modifier
+            }
+            found
+        } else {
+            self.peek().is_some_and(|c| "^$*%".contains(c))
+        };
+        let mut text = if designator {
+            self.words(&words, matched)
+                .ok_or_else(|| format!("{}: bad word specifier", &self.line[start..self.pos]))?
+        } else {
+            event
+        };
+
+        while self.eat(':') {
+            self.modifier(&mut text, start)?;
+        }
+        Ok(text)
+    }
+
+    /// Parses the event designator and returns the history line and, for `!?sub?`, the search string.
+    fn event(&mut self, start: usize) -> Result<(String, Option<String>), String> {
+        let not_found = |this: &Self| format!("{}: event not found", &this.line[start..this.pos]);
+        let last = |this: &Self| this.history.last().cloned().ok_or_else(|| not_found(this));
+
+        match self.peek() {
+            Some('!') => {
+                self.bump();
+                Ok((last(self)?, None))
+            }
+            // `!$`, `!:1` and friends refer to the previous command
+            Some('^' | '$' | '*' | '%' | ':') => Ok((last(self)?, None)),
+            Some('#') => {
+                self.bump();
+                Ok((self.line[..start].to_string(), None))
+            }
+            Some('-') | Some('0'..='9') => {
+                let negative = self.eat('-');
+                let n = self.number().ok_or_else(|| not_found(self))?;
+                let index = if negative { self.history.len().checked_sub(n) } else { n.checked_sub(1) };
+                match index.and_then(|i| self.history.get(i)) {
+                    Some(entry) => Ok((entry.clone(), None)),
+                    None => Err(not_found(self)),
+                }
+            }
+            Some('?') => {
+                self.bump();
+                let end = self.line[self.pos..].find(['?', '\n']).map_or(self.line.len(), |e| self.pos + e);
+                let needle = self.line[self.pos..end].to_string();
+                self.pos = end;
+                self.eat('?');
+                match self.history.iter().rev().find(|h| h.contains(&needle)) {
+                    Some(entry) if !needle.is_empty() => Ok((entry.clone(), Some(needle))),
+                    _ => Err(not_found(self)),
+                }
+            }
+            _ => {
+                let end = self.line[self.pos..]
+                    .find(|c| EVENT_DELIMITERS.contains(c))
+                    .map_or(self.line.len(), |e| self.pos + e);
+                let prefix = &self.line[self.pos..end];
+                self.pos = end;
+                match self.history.iter().rev().find(|h| h.starts_with(prefix)) {
+                    Some(entry) => Ok((entry.clone(), None)),
+                    None => Err(not_found(self)),
+                }
+            }
+        }
+    }
+
+    /// Parses a word designator (`n`, `^`, `$`, `%`, `x-y`, `x-`, `-y`, `*`, `x*`) and joins the selected words.
+    fn words(&mut self, words: &[&str], matched: Option<usize>) -> Option<String> {
+        let last = words.len().checked_sub(1)?;
+        let (first, end) = if self.eat('*') {
+            if last == 0 {
+                return Some(String::new());
+            }
+            (1, last)
+        } else {
+            let first = if self.peek() == Some('-') { 0 } else { self.endpoint(last, matched)? };
+            if self.eat('-') {
+                let end = match self.peek() {
+                    Some('0'..='9' | '^' | '$' | '%') => self.endpoint(last, matched)?,
+                    _ => last.checked_sub(1)?, // `x-` omits the last word
+                };
+                (first, end)
+            } else if self.eat('*') {
+                (first, last)
+            } else {
+                (first, first)
+            }
+        };
+        if first > end || end > last {
+            return None;
+        }
+        Some(words[first..=end].join(" "))
+    }
+
+    fn endpoint(&mut self, last: usize, matched: Option<usize>) -> Option<usize> {
+        match self.peek()? {
+            '^' => { self.bump(); Some(1) }
+            '$' => { self.bump(); Some(last) }
+            '%' => { self.bump(); matched }
+            _ => self.number(),
+        }
+    }
+
+    fn modifier(&mut self, text: &mut String, start: usize) -> Result<(), String> {
+        let Some(m) = self.bump() else {
+            return Err(format!("{}: unrecognized history modifier", &self.line[start..self.pos]));
+        };
+        match m {
+            'h' => {
+                if let Some(slash) = text.rfind('/') {
+                    text.truncate(slash.max(1));
+                }
+            }
+            't' => {
+                if let Some(slash) = text.rfind('/') {
+                    text.drain(..=slash);
+                }
+            }
+            'r' => {
+                if let Some(dot) = suffix_start(text) {
+                    text.truncate(dot);
+                }
+            }
+            'e' => {
+                if let Some(dot) = suffix_start(text) {
+                    text.drain(..dot);
+                }
+            }
+            'p' => self.print_only = true,
+            'q' => *text = quote(text),
+            'x' => *text = text.split_whitespace().map(quote).collect::<Vec<_>>().join(" "),
+            's' => self.substitute(text, false, start)?,
+            '&' => self.repeat_substitution(text, false, start)?,
+            'g' | 'G' => match self.bump() {
+                Some('s') => self.substitute(text, true, start)?,
+                Some('&') => self.repeat_substitution(text, true, start)?,
+                _ => return Err(format!("{}: unrecognized history modifier", &self.line[start..self.pos])),
+            },
+            _ => return Err(format!("{}: unrecognized history modifier", &self.line[start..self.pos])),
+        }
+        Ok(())
+    }
+
+    /// `s/old/new/`: any character can be the delimiter, `\` escapes it and `&` in `new` stands for `old`.
+    /// The final delimiter may be left off at the end of the line.
+    fn substitute(&mut self, text: &mut String, global: bool, start: usize) -> Result<(), String> {
+        let Some(delimiter) = self.bump() else {
+            return Err(format!("{}: substitution failed", &self.line[start..self.pos]));
+        };
+        let old = self.delimited(delimiter, false);
+        let new = self.delimited(delimiter, true);
+        let old = if old.is_empty() {
+            match LAST_SUBST.lock().unwrap().as_ref() {
+                Some((last_old, _)) => last_old.clone(),
+                None => return Err(format!("{}: no previous substitution", &self.line[start..self.pos])),
+            }
+        } else {
+            old
+        };
+        let new = new.replace('\x00', &old);
+        *LAST_SUBST.lock().unwrap() = Some((old, new));
+        self.repeat_substitution(text, global, start)
+    }
+
+    fn repeat_substitution(&mut self, text: &mut String, global: bool, start: usize) -> Result<(), String> {
+        let failed = || format!("{}: substitution failed", &self.line[start..self.pos]);
+        let Some((old, new)) = LAST_SUBST.lock().unwrap().clone() else {
+            return Err(failed());
+        };
+        if !text.contains(&old) {
+            return Err(failed());
+        }
+        *text = if global { text.replace(&old, &new) } else { text.replacen(&old, &new, 1) };
+        Ok(())
+    }
+
+    /// Reads up to (and past) the next unescaped `delimiter`. In the replacement an unescaped `&`
+    /// is marked with NUL, to be replaced by the pattern once it is known.
+    fn delimited(&mut self, delimiter: char, replacement: bool) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                _ if c == delimiter => break,
+                '\\' if matches!(self.peek(), Some(n) if n == delimiter || n == '&') => {
+                    s.push(self.bump().unwrap());
+                }
+                '&' if replacement => s.push('\x00'),
+                '\n' => {
+                    self.pos -= 1;
+                    break;
+                }
+                _ => s.push(c),
+            }
+        }
+        s
+    }
+}
+
+/// Start of a trailing `.suffix` in the last path component.
+fn suffix_start(path: &str) -> Option<usize> {
+    let dot = path.rfind('.')?;
+    (dot > path.rfind('/').map_or(0, |s| s + 1)).then_some(dot)
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<String> {
+        ["ls /usr/lib/file.tar.gz", "echo one two three", "git status"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn expanded(line: &str) -> String {
+        expand(line, &history()).unwrap().line
+    }
+
+    #[test]
+    fn test_events() {
+        assert_eq!(expanded("sudo !!"), "sudo git status");
+        assert_eq!(expanded("!1"), "ls /usr/lib/file.tar.gz");
+        assert_eq!(expanded("!-2"), "echo one two three");
+        assert_eq!(expanded("!ec"), "echo one two three");
+        assert_eq!(expanded("!?two?"), "echo one two three");
+        assert_eq!(expand("!nope", &history()), Err("!nope: event not found".to_string()));
+    }
+
+    #[test]
+    fn test_word_designators() {
+        assert_eq!(expanded("cat !$"), "cat status");
+        assert_eq!(expanded("!-2:1-2"), "one two");
+        assert_eq!(expanded("!-2:*"), "one two three");
+        assert_eq!(expanded("!-2:2*"), "two three");
+        assert_eq!(expanded("!-2:2-"), "two");
+        assert_eq!(expanded("!-2^"), "one");
+        assert_eq!(expanded("!?tw?:%"), "two");
+        assert!(expand("!!:9", &history()).is_err());
+    }
+
+    #[test]
+    fn test_modifiers() {
+        assert_eq!(expanded("!1:$:h"), "/usr/lib");
+        assert_eq!(expanded("!1:$:t"), "file.tar.gz");
+        assert_eq!(expanded("!1:$:r"), "/usr/lib/file.tar");
+        assert_eq!(expanded("!1:$:e"), ".gz");
+        assert_eq!(expanded("!-2:gs/o/0/"), "ech0 0ne tw0 three");
+        assert_eq!(expanded("!-2:s/one/[&]"), "echo [one] two three");
+        assert_eq!(expanded("^status^log^ -1"), "git log -1");
+        assert!(expand("!!:p", &history()).unwrap().print_only);
+    }
+
+    #[test]
+    fn test_quoting_suppresses() {
+        let e = expand("echo '!!' \\!! a!= \"!\"", &history()).unwrap();
+        assert_eq!(e.line, "echo '!!' \\!! a!= \"!\"");
+        assert!(!e.expanded);
+        assert_eq!(expanded("echo \"!!\""), "echo \"git status\"");
+    }
+}
@@ -1,11 +1,37 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
-use std::sync::{Mutex, OnceLock};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rustyline::history::{SearchDirection, SearchResult};
+
+use crate::{dirs, histdb, pattern, sys};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+#[derive(Default)]
+struct Entry {
+    command: String,
+    /// Directory the command was entered in; unknown for entries read from a file.
+    cwd: Option<PathBuf>,
+    /// Seconds since the epoch; unknown for file entries without a `#<epoch>` line.
+    time: Option<i64>,
+    /// Exit status and run time, set by `finish` for commands run in this session.
+    status: Option<i32>,
+    duration_ms: Option<u64>,
+}
 
 struct History {
-    commands: Vec<String>,
+    entries: Vec<Entry>,
     unsaved_idx: usize,
+    max_size: usize,
+    /// Lines of each history file already read or written, for `history -n`.
+    file_lines: HashMap<PathBuf, usize>,
+    /// The last entry was just added by `add` and is waiting for `finish`.
+    pending: bool,
 }
 
 static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
@@ -13,109 +39,729 @@ static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
 const MAX_HISTORY_SIZE: usize = 1000;
 const MAX_COMMAND_LEN: usize = 1024;
 
-/// Adds a command to the global history.
-/// Should be called from your main loop after reading input.
+fn lock() -> MutexGuard<'static, History> {
+    HISTORY
+        .get_or_init(|| Mutex::new(History {
+            entries: Vec::new(),
+            unsaved_idx: 0,
+            max_size: MAX_HISTORY_SIZE,
+            file_lines: HashMap::new(),
+            pending: false,
+        }))
+        .lock()
+        .unwrap()
+}
+
+/// Adds a command to the global history, unless `$HISTCONTROL` or `$HISTIGNORE` exclude it.
+/// Should be called from your main loop after reading input (with leading blanks kept, for `ignorespace`).
 pub fn add(command: &str) {
-    let history_mutex = HISTORY.get_or_init(|| Mutex::new(History { commands: Vec::new(), unsaved_idx: 0 }));
-    let mut history = history_mutex.lock().unwrap();
-    add_internal(&mut history, command);
+    let mut history = lock();
+    history.pending = false;
+    let control = env::var("HISTCONTROL").unwrap_or_default();
+    let control: Vec<&str> = control.split(':').collect();
+    let ignore_space = control.iter().any(|c| matches!(*c, "ignorespace" | "ignoreboth"));
+    let ignore_dups = control.iter().any(|c| matches!(*c, "ignoredups" | "ignoreboth"));
+
+    if ignore_space && command.starts_with([' ', '\t']) {
+        return;
+    }
+    let command = command.trim_start();
+    let previous = history.entries.last().map(|e| e.command.as_str());
+    if ignore_dups && previous == Some(command) {
+        return;
+    }
+    if let Ok(ignore) = env::var("HISTIGNORE")
+        && split_patterns(&ignore).iter().any(|p| {
+            // `&` stands for the previous history line
+            if p == "&" { previous == Some(command) } else { pattern::matches(p, command) }
+        })
+    {
+        return;
+    }
+    if control.contains(&"erasedups") {
+        let mut i = 0;
+        while i < history.entries.len() {
+            if history.entries[i].command == command {
+                history.entries.remove(i);
+                if i < history.unsaved_idx {
+                    history.unsaved_idx -= 1;
+                }
+            } else {
+                i += 1;
+            }
+        }
+    }
+    history.pending = add_internal(&mut history, command, Some(dirs::cwd()), Some(sys::now()))
+        && !history.entries.is_empty(); // HISTSIZE=0 keeps nothing
 }
 
-fn add_internal(history: &mut History, command: &str) {
-    if command.trim().is_empty() {
+/// Records how the command line just passed to `add` ended, and appends it to the `$HISTDB` database.
+/// Does nothing if `add` left it out of the history (e.g. `HISTCONTROL=ignorespace`).
+pub fn finish(status: i32, duration: Duration) {
+    let mut history = lock();
+    if !std::mem::take(&mut history.pending) {
         return;
     }
+    let Some(entry) = history.entries.last_mut() else {
+        return;
+    };
+    entry.status = Some(status);
+    entry.duration_ms = Some(duration.as_millis() as u64);
+    let record = to_record(entry);
+    drop(history);
+    if let Some(path) = histdb::path() {
+        _ = histdb::append(&path, &record);
+    }
+}
+
+fn to_record(entry: &Entry) -> histdb::Record {
+    histdb::Record {
+        command: entry.command.clone(),
+        cwd: entry.cwd.as_ref().map(|p| p.display().to_string()),
+        status: entry.status,
+        start: entry.time,
+        duration_ms: entry.duration_ms,
+        host: sys::hostname(),
+        session: histdb::session_id().to_string(),
+    }
+}
+
+/// The in-memory history as database records, for `history search` without `$HISTDB`.
+pub fn records() -> Vec<histdb::Record> {
+    lock().entries.iter().map(to_record).collect()
+}
 
-    // Truncate long commands if necessary
+/// Splits `$HISTIGNORE` at unescaped colons.
+fn split_patterns(s: &str) -> Vec<String> {
+    let mut patterns = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.clone().next() == Some(':') => {
+                chars.next();
+                patterns.last_mut().unwrap().push(':');
+            }
+            ':' => patterns.push(String::new()),
+            _ => patterns.last_mut().unwrap().push(c),
+        }
+    }
+    patterns.retain(|p| !p.is_empty());
+    patterns
+}
+
+/// Returns false if the command was empty and not added.
+fn add_internal(history: &mut History, command: &str, cwd: Option<PathBuf>, time: Option<i64>) -> bool {
+    if command.trim().is_empty() {
+        return false;
+    }
+
+    // Truncate long commands if necessary, without splitting a multi-byte character
     let cmd_string = if command.len() > MAX_COMMAND_LEN {
-        format!("{}...", &command[..MAX_COMMAND_LEN])
+        format!("{}...", &command[..command.floor_char_boundary(MAX_COMMAND_LEN)])
     } else {
         command.to_string()
     };
 
-    history.commands.push(cmd_string);
-    if history.commands.len() > MAX_HISTORY_SIZE {
-        history.commands.remove(0);
-        if history.unsaved_idx > 0 {
-            history.unsaved_idx -= 1;
+    history.entries.push(Entry { command: cmd_string, cwd, time, ..Default::default() });
+    trim_to_size(history);
+    true
+}
+
+/// Drops the oldest entries beyond `$HISTSIZE` (or `max_size` when it isn't a number).
+fn trim_to_size(history: &mut History) {
+    let max_size = size_var("HISTSIZE").unwrap_or(Some(history.max_size)).unwrap_or(usize::MAX);
+    let excess = history.entries.len().saturating_sub(max_size);
+    if excess > 0 {
+        history.entries.drain(..excess);
+        history.unsaved_idx = history.unsaved_idx.saturating_sub(excess);
+    }
+}
+
+/// A size variable: `None` if it is unset or not a number, `Some(None)` (no limit) if negative.
+fn size_var(name: &str) -> Option<Option<usize>> {
+    let n: i64 = env::var(name).ok()?.trim().parse().ok()?;
+    Some(usize::try_from(n).ok())
+}
+
+/// Cuts a history file down to its last `$HISTFILESIZE` entries (a `#<epoch>` line stays with its command).
+/// When that is unset the in-memory limit is used, as bash defaults one to the other.
+/// The caller holds the file's lock.
+fn truncate_file(history: &mut History, path: &Path) -> io::Result<()> {
+    let limit = match size_var("HISTFILESIZE") {
+        Some(limit) => limit,
+        None if env::var_os("HISTFILESIZE").is_some() => None, // set but not a number
+        None => size_var("HISTSIZE").unwrap_or(Some(history.max_size)),
+    };
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+    let content = fs::read_to_string(path)?;
+    let lines: Vec<&str> = content.lines().collect();
+    let starts: Vec<usize> = parse_entries(&lines).into_iter().map(|(start, _)| start).collect();
+    if starts.len() <= limit {
+        return Ok(());
+    }
+    let kept = &lines[starts[starts.len() - limit]..];
+    let mut new_content = String::new();
+    for line in kept {
+        new_content.push_str(line);
+        new_content.push('\n');
+    }
+    replace_file(path, &new_content)?;
+    if let Some(known) = history.file_lines.get_mut(path) {
+        let unknown = lines.len().saturating_sub(*known);
+        *known = kept.len().saturating_sub(unknown);
+    }
+    Ok(())
+}
+
+/// An exclusive advisory lock on `<histfile>.lock`, released when dropped.
+/// Every read and write of a history file takes it, so sessions sharing a file never interleave.
+/// (A separate file, because `replace_file` swaps out the history file itself.)
+/// The lock file is removed again on drop, so none is left behind next to the history file.
+pub(crate) struct FileLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Unlinked while still locked: a waiter then finds its file gone and starts over.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub(crate) fn lock_file(path: &Path) -> io::Result<FileLock> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    loop {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+        sys::flock_exclusive(&file)?;
+        // The holder we waited for may have removed the file; only the one at the path counts.
+        let locked = file.metadata()?;
+        if let Ok(current) = fs::metadata(&lock_path)
+            && current.dev() == locked.dev()
+            && current.ino() == locked.ino()
+        {
+            return Ok(FileLock { path: lock_path, _file: file });
+        }
+    }
+}
+
+/// Replaces the file atomically by writing a temporary file next to it and renaming it over the original,
+/// so a crash or a concurrent reader never sees a half-written file.
+pub(crate) fn replace_file(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    let result = fs::write(&tmp, content).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// `#<epoch>`, the line written before a command when `$HISTTIMEFORMAT` is set. Older files used `#0` for an unknown time.
+fn parse_timestamp(line: &str) -> Option<i64> {
+    let digits = line.strip_prefix('#')?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Appends an entry and returns the number of lines added. Like bash, its time goes on a `#<epoch>` line
+/// before it only when `timestamps` (`$HISTTIMEFORMAT` is set), so a plain file keeps one line per command.
+/// A multi-line command ends each line but its last with an escaping `\`. So that this stays unambiguous,
+/// a run of backslashes at the end of any of its lines is doubled: an odd run continues the entry.
+fn format_entry(out: &mut String, entry: &Entry, timestamps: bool) -> usize {
+    let mut lines = 0;
+    if timestamps && let Some(time) = entry.time {
+        out.push_str(&format!("#{time}\n"));
+        lines += 1;
+    }
+    let mut command_lines = entry.command.split('\n').peekable();
+    while let Some(line) = command_lines.next() {
+        out.push_str(line);
+        out.push_str(&"\\".repeat(trailing_backslashes(line)));
+        if command_lines.peek().is_some() {
+            out.push('\\');
         }
+        out.push('\n');
+        lines += 1;
     }
+    lines
 }
 
-/// Prints the current history to stdout.
+fn trailing_backslashes(line: &str) -> usize {
+    line.len() - line.trim_end_matches('\\').len()
+}
+
+/// Whether history files get timestamp lines.
+fn write_timestamps() -> bool {
+    env::var_os("HISTTIMEFORMAT").is_some()
+}
+
+/// Groups history file lines into entries, returning each with the index of its first line.
+/// A `#<epoch>` line gives the time of the command after it. A line ending in an odd number of backslashes
+/// continues on the next one (see `format_entry`), and every other line starts an entry.
+fn parse_entries(lines: &[&str]) -> Vec<(usize, Entry)> {
+    let mut entries: Vec<(usize, Entry)> = Vec::new();
+    let mut time: Option<(usize, i64)> = None; // a timestamp line waiting for its command
+    let mut continued = false; // the last line ended in an escaped newline
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if !continued && let Some(t) = parse_timestamp(line) {
+            time = Some((i, t));
+            continue;
+        }
+        let backslashes = trailing_backslashes(line);
+        let text = &line[..line.len() - backslashes + backslashes / 2];
+        if continued {
+            let entry = &mut entries.last_mut().unwrap().1;
+            entry.command.push('\n');
+            entry.command.push_str(text);
+        } else {
+            let (start, time) = match time.take() {
+                Some((start, t)) => (start, (t != 0).then_some(t)),
+                None => (i, None),
+            };
+            entries.push((start, Entry { command: text.to_string(), time, ..Default::default() }));
+        }
+        continued = backslashes % 2 == 1;
+    }
+    entries
+}
+
+/// Parses the file's lines after the first `skip` into entries.
+/// Also returns the number of complete lines in the file: a last line without its newline
+/// is still being written by another session, so it is left for the next read.
+fn read_file_entries(path: &Path, skip: usize) -> io::Result<(Vec<Entry>, usize)> {
+    let content = fs::read_to_string(path)?;
+    let complete: Vec<&str> = content.split_inclusive('\n').filter_map(|l| l.strip_suffix('\n')).collect();
+    let entries = parse_entries(complete.get(skip..).unwrap_or_default());
+    Ok((entries.into_iter().map(|(_, entry)| entry).collect(), complete.len()))
+}
+
+/// The number of complete lines in the file, as `read_file_entries` counts them.
+fn count_lines(path: &Path) -> io::Result<usize> {
+    Ok(fs::read(path)?.iter().filter(|&&b| b == b'\n').count())
+}
+
+/// Appends the entries not saved yet to the file with a single write, under its lock.
+/// Returns the number of lines written.
+fn append_unsaved(history: &mut History, path: &Path) -> io::Result<usize> {
+    let mut content = String::new();
+    let mut written = 0;
+    let timestamps = write_timestamps();
+    for entry in history.entries.iter().skip(history.unsaved_idx) {
+        written += format_entry(&mut content, entry, timestamps);
+    }
+    if written > 0 {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(content.as_bytes())?;
+    }
+    history.unsaved_idx = history.entries.len();
+    Ok(written)
+}
+
+/// Prints the current history to stdout, with each entry's time formatted by `$HISTTIMEFORMAT` if set.
 /// To be called by the `history` builtin.
 pub fn print(stdout: &mut dyn Write, limit: Option<usize>) {
-    let history_mutex = HISTORY.get_or_init(|| Mutex::new(History { commands: Vec::new(), unsaved_idx: 0 }));
-    let history = history_mutex.lock().unwrap();
+    let history = lock();
+    let time_format = env::var("HISTTIMEFORMAT").ok();
 
     let start_index = match limit {
-        Some(n) => history.commands.len().saturating_sub(n),
+        Some(n) => history.entries.len().saturating_sub(n),
         None => 0,
     };
 
-    for (i, command) in history.commands.iter().enumerate().skip(start_index) {
-        let _ = writeln!(stdout, "{:5}  {}", i + 1, command);
+    for (i, entry) in history.entries.iter().enumerate().skip(start_index) {
+        let time = match (&time_format, entry.time) {
+            (None, _) => String::new(),
+            (Some(format), Some(time)) => sys::strftime(format, time),
+            (Some(_), None) => "??".to_string(), // as bash shows entries read without a timestamp
+        };
+        let _ = writeln!(stdout, "{:5}  {}{}", i + 1, time, entry.command);
     }
 }
 
-/// Writes the current history to a file.
-pub fn write_to_file(path: &Path) -> std::io::Result<()> {
-    let history_mutex = HISTORY.get_or_init(|| Mutex::new(History { commands: Vec::new(), unsaved_idx: 0 }));
-    let mut history = history_mutex.lock().unwrap();
+/// Writes the current history to a file, replacing it atomically.
+pub fn write_to_file(path: &Path) -> io::Result<()> {
+    let mut history = lock();
+    let _lock = lock_file(path)?;
 
-    let mut file = File::create(path)?;
-    for command in history.commands.iter() {
-        writeln!(file, "{}", command)?;
+    let mut content = String::new();
+    let mut written = 0;
+    let timestamps = write_timestamps();
+    for entry in history.entries.iter() {
+        written += format_entry(&mut content, entry, timestamps);
     }
+    replace_file(path, &content)?;
     // We wrote everything, so everything is now saved.
-    history.unsaved_idx = history.commands.len();
-    Ok(())
+    history.unsaved_idx = history.entries.len();
+    history.file_lines.insert(path.to_path_buf(), written);
+    truncate_file(&mut history, path)
 }
 
 /// Reads history from a file, appending to the current history.
-pub fn read_from_file(path: &Path) -> std::io::Result<usize> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let history_mutex = HISTORY.get_or_init(|| Mutex::new(History { commands: Vec::new(), unsaved_idx: 0 }));
-    let mut history = history_mutex.lock().unwrap();
+pub fn read_from_file(path: &Path) -> io::Result<usize> {
+    read_lines_from(path, 0)
+}
+
+/// `history -n`: reads only the lines appended to the file since it was last read or written,
+/// e.g. by another shell session.
+pub fn read_new_from_file(path: &Path) -> io::Result<usize> {
+    let known = lock().file_lines.get(path).copied().unwrap_or(0);
+    read_lines_from(path, known)
+}
+
+fn read_lines_from(path: &Path, skip: usize) -> io::Result<usize> {
+    let mut history = lock();
+    let (entries, lines) = {
+        let _lock = lock_file(path)?;
+        read_file_entries(path, skip)?
+    };
 
     // If we are reading history and everything before was saved (e.g. startup),
     // we treat the loaded commands as saved as well.
-    let was_fully_saved = history.unsaved_idx == history.commands.len();
+    let was_fully_saved = history.unsaved_idx == history.entries.len();
 
-    let mut count = 0;
-    for line in reader.lines() {
-        add_internal(&mut history, &line?);
-        count += 1;
+    let count = entries.len();
+    for entry in entries {
+        add_internal(&mut history, &entry.command, None, entry.time);
     }
 
     if was_fully_saved {
-        history.unsaved_idx = history.commands.len();
+        history.unsaved_idx = history.entries.len();
     }
+    history.file_lines.insert(path.to_path_buf(), lines);
     Ok(count)
 }
 
 /// Appends the current history to a file.
-pub fn append_to_file(path: &Path) -> std::io::Result<()> {
-    let history_mutex = HISTORY.get_or_init(|| Mutex::new(History { commands: Vec::new(), unsaved_idx: 0 }));
-    let mut history = history_mutex.lock().unwrap();
-
-    let mut file = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)?;
+pub fn append_to_file(path: &Path) -> io::Result<()> {
+    append_locked(&mut lock(), path)
+}
+
+fn append_locked(history: &mut History, path: &Path) -> io::Result<()> {
+    let _lock = lock_file(path)?;
+
     // Only write commands that haven't been saved yet
-    for command in history.commands.iter().skip(history.unsaved_idx) {
-        writeln!(file, "{}", command)?;
+    append_unsaved(history, path)?;
+    // Count the file again rather than adding what we wrote: other sessions may have appended since we
+    // last looked, and `history -n` must then start after our lines, not somewhere inside them.
+    let lines = match count_lines(path) {
+        Ok(lines) => lines,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0, // nothing to write and no file yet
+        Err(e) => return Err(e),
+    };
+    history.file_lines.insert(path.to_path_buf(), lines);
+    truncate_file(history, path)
+}
+
+/// `set -o share_history`, run before each prompt: under one lock, reads the entries other sessions
+/// appended since we last looked and appends ours. Theirs go before our unsaved entries,
+/// so the in-memory order matches the file. Returns the number of entries read.
+pub fn sync_with_file(path: &Path) -> io::Result<usize> {
+    let mut history = lock();
+    let _lock = lock_file(path)?;
+
+    let known = history.file_lines.get(path).copied().unwrap_or(0);
+    let (theirs, lines) = match read_file_entries(path, known) {
+        Ok(read) => read,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (Vec::new(), 0),
+        Err(e) => return Err(e),
+    };
+    let ours_start = history.unsaved_idx;
+    let written = append_unsaved(&mut history, path)?;
+
+    let count = theirs.len();
+    if count > 0 {
+        let ours: Vec<Entry> = history.entries.drain(ours_start..).collect();
+        for entry in theirs {
+            add_internal(&mut history, &entry.command, None, entry.time);
+        }
+        history.entries.extend(ours);
+        history.unsaved_idx = history.entries.len();
+        trim_to_size(&mut history);
     }
-    history.unsaved_idx = history.commands.len();
-    Ok(())
+    history.file_lines.insert(path.to_path_buf(), lines + written);
+    truncate_file(&mut history, path)?;
+    Ok(count)
 }
 
 /// Returns the last `n` commands from the history.
 pub fn get_recent(n: usize) -> Vec<String> {
-    let history_mutex = HISTORY.get_or_init(|| Mutex::new(History { commands: Vec::new(), unsaved_idx: 0 }));
-    let history = history_mutex.lock().unwrap();
-    let start_index = history.commands.len().saturating_sub(n);
-    history.commands[start_index..].to_vec()
+    let history = lock();
+    let start_index = history.entries.len().saturating_sub(n);
+    history.entries[start_index..].iter().map(|e| e.command.clone()).collect()
+}
+
+/// Deletes entries `start..=end` (0-based). Returns false if the range is out of bounds.
+pub fn delete_range(start: usize, end: usize) -> bool {
+    let mut history = lock();
+    if start > end || end >= history.entries.len() {
+        return false;
+    }
+    history.entries.drain(start..=end);
+    history.pending = false;
+    // Deleted entries that were not saved yet no longer need saving; saved ones shift the mark.
+    let unsaved_idx = history.unsaved_idx;
+    history.unsaved_idx = if unsaved_idx > end {
+        unsaved_idx - (end - start + 1)
+    } else {
+        unsaved_idx.min(start)
+    };
+    true
+}
+
+/// Removes the entry `add` recorded for the command line being run (the `history -s`/`-p` line itself).
+/// Does nothing if `add` left that line out, e.g. for `HISTCONTROL=ignorespace` or `HISTIGNORE`.
+pub fn remove_current() {
+    remove_pending(&mut lock());
+}
+
+fn remove_pending(history: &mut History) {
+    if std::mem::take(&mut history.pending) {
+        history.entries.pop();
+        history.unsaved_idx = history.unsaved_idx.min(history.entries.len());
+    }
+}
+
+/// Removes all entries from the in-memory history.
+pub fn clear() {
+    let mut history = lock();
+    history.entries.clear();
+    history.unsaved_idx = 0;
+    history.pending = false;
+}
+
+/// Number of entries currently in the history.
+pub fn len() -> usize {
+    lock().entries.len()
+}
+
+/// The rest of the most recent command starting with `prefix`, for autosuggestions.
+/// Commands entered in `cwd` win over more recent ones entered elsewhere.
+pub fn suggest(prefix: &str, cwd: Option<&Path>) -> Option<String> {
+    if prefix.is_empty() {
+        return None;
+    }
+    let history = lock();
+
+    let mut matches = history.entries.iter().rev()
+        .filter(|e| e.command.len() > prefix.len() && e.command.starts_with(prefix));
+    let best = match cwd {
+        Some(cwd) => matches.clone().find(|e| e.cwd.as_deref() == Some(cwd)).or_else(|| matches.next()),
+        None => matches.next(),
+    };
+    best.map(|e| e.command[prefix.len()..].to_string())
+}
+
+/// Lets rustyline navigate (Up/Down, Ctrl-R) this module's history directly,
+/// so whatever the `history` builtin does (`-r`, `-c`, `-d`, ...) shows up immediately.
+/// The module stays the single source of truth; this is just a view onto it.
+pub struct SharedHistory;
+
+impl SharedHistory {
+    fn search_by<F>(term: &str, start: usize, dir: SearchDirection, test: F) -> Option<SearchResult<'static>>
+    where
+        F: Fn(&str) -> Option<usize>,
+    {
+        let history = lock();
+        if term.is_empty() || start >= history.entries.len() {
+            return None;
+        }
+        let found = |idx: usize| {
+            let command = &history.entries[idx].command;
+            test(command).map(|pos| SearchResult { entry: Cow::Owned(command.clone()), idx, pos })
+        };
+        match dir {
+            SearchDirection::Reverse => (0..=start).rev().find_map(found),
+            SearchDirection::Forward => (start..history.entries.len()).find_map(found),
+        }
+    }
+}
+
+impl rustyline::history::History for SharedHistory {
+    fn get(&self, index: usize, _dir: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        let history = lock();
+        Ok(history.entries.get(index).map(|e| SearchResult {
+            entry: Cow::Owned(e.command.clone()),
+            idx: index,
+            pos: 0,
+        }))
+    }
+
+    fn add(&mut self, line: &str) -> rustyline::Result<bool> {
+        let before = len();
+        add(line);
+        Ok(len() != before)
+    }
+
+    fn add_owned(&mut self, line: String) -> rustyline::Result<bool> {
+        self.add(&line)
+    }
+
+    fn len(&self) -> usize {
+        len()
+    }
+
+    fn is_empty(&self) -> bool {
+        len() == 0
+    }
+
+    fn set_max_len(&mut self, max_len: usize) -> rustyline::Result<()> {
+        let mut history = lock();
+        history.max_size = max_len;
+        trim_to_size(&mut history);
+        Ok(())
+    }
+
+    fn ignore_dups(&mut self, _yes: bool) -> rustyline::Result<()> {
+        Ok(())
+    }
+
+    fn ignore_space(&mut self, _yes: bool) {}
+
+    fn save(&mut self, path: &Path) -> rustyline::Result<()> {
+        Ok(write_to_file(path)?)
+    }
+
+    fn append(&mut self, path: &Path) -> rustyline::Result<()> {
+        Ok(append_to_file(path)?)
+    }
+
+    fn load(&mut self, path: &Path) -> rustyline::Result<()> {
+        read_from_file(path)?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> rustyline::Result<()> {
+        clear();
+        Ok(())
+    }
+
+    fn search(&self, term: &str, start: usize, dir: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(Self::search_by(term, start, dir, |entry| entry.find(term)))
+    }
+
+    fn starts_with(&self, term: &str, start: usize, dir: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(Self::search_by(term, start, dir, |entry| entry.starts_with(term).then_some(term.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_file_leaves_no_sidecar() {
+        let path = env::temp_dir().join(format!("shell_lock_test_{}", std::process::id()));
+        let lock_path = PathBuf::from(format!("{}.lock", path.display()));
+        {
+            let _lock = lock_file(&path).unwrap();
+            assert!(lock_path.exists());
+        }
+        assert!(!lock_path.exists());
+        let _again = lock_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append_counts_lines_of_other_sessions() {
+        let path = env::temp_dir().join(format!("shell_append_test_{}", std::process::id()));
+        let mut history = History { entries: Vec::new(), unsaved_idx: 0, max_size: 10, file_lines: HashMap::new(), pending: false };
+        add_internal(&mut history, "echo ours", None, None);
+        append_locked(&mut history, &path).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"echo theirs\n").unwrap();
+        add_internal(&mut history, "echo 'a\nb'", None, None);
+        append_locked(&mut history, &path).unwrap();
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        _ = fs::remove_file(&path);
+        assert_eq!(lines, 4);
+        assert_eq!(history.file_lines.get(&path), Some(&lines));
+    }
+
+    #[test]
+    fn test_multi_line_entries_round_trip() {
+        let written = [
+            Entry { command: "echo 'a\nb'".to_string(), time: Some(1700000000), ..Default::default() },
+            Entry { command: "ls".to_string(), ..Default::default() },
+        ];
+        let mut content = String::new();
+        let lines: usize = written.iter().map(|e| format_entry(&mut content, e, true)).sum();
+        assert_eq!(content, "#1700000000\necho 'a\\\nb'\nls\n");
+        assert_eq!(lines, 4);
+
+        let read = parse_entries(&content.lines().collect::<Vec<_>>());
+        assert_eq!(read.iter().map(|(start, _)| *start).collect::<Vec<_>>(), [0, 3]);
+        assert_eq!(read[0].1.command, "echo 'a\nb'");
+        assert_eq!(read[0].1.time, Some(1700000000));
+        assert_eq!(read[1].1.command, "ls");
+        assert_eq!(read[1].1.time, None);
+
+        // without HISTTIMEFORMAT: one line per line of command, nothing else
+        let mut content = String::new();
+        let lines: usize = written.iter().map(|e| format_entry(&mut content, e, false)).sum();
+        assert_eq!(content, "echo 'a\\\nb'\nls\n");
+        assert_eq!(lines, 3);
+        let read = parse_entries(&content.lines().collect::<Vec<_>>());
+        assert_eq!(read.iter().map(|(start, e)| (*start, e.command.as_str())).collect::<Vec<_>>(),
+            [(0, "echo 'a\nb'"), (2, "ls")]);
+    }
+
+    #[test]
+    fn test_unterminated_entries_round_trip() {
+        let commands = ["echo \"abc", "echo one", "ls |", "echo a\\", "printf '%s\\\n#5\n\\\\' x", "exit"];
+        let written: Vec<Entry> =
+            commands.iter().map(|c| Entry { command: c.to_string(), ..Default::default() }).collect();
+        let mut content = String::new();
+        let lines: usize = written.iter().map(|e| format_entry(&mut content, e, false)).sum();
+        assert_eq!(lines, content.lines().count());
+        let read = parse_entries(&content.lines().collect::<Vec<_>>());
+        assert_eq!(read.iter().map(|(_, e)| e.command.as_str()).collect::<Vec<_>>(), commands);
+    }
+
+    #[test]
+    fn test_plain_lines_are_separate_entries() {
+        let read = parse_entries(&["ls", "pwd", "echo \"a", "b\" |", "wc"]);
+        assert_eq!(read.len(), 5);
+    }
+
+    #[test]
+    fn test_mixed_timestamped_and_plain_lines() {
+        let read = parse_entries(&["#1700000000", "ls", "pwd", "echo \"a\\", "#5\\", "b\" |", "wc", "#1700000001", "cd"]);
+        let commands: Vec<(usize, &str, Option<i64>)> =
+            read.iter().map(|(start, e)| (*start, e.command.as_str(), e.time)).collect();
+        assert_eq!(commands, [
+            (0, "ls", Some(1700000000)),
+            (2, "pwd", None),
+            (3, "echo \"a\n#5\nb\" |", None),
+            (6, "wc", None),
+            (7, "cd", Some(1700000001)),
+        ]);
+    }
+
+    #[test]
+    fn test_truncation_keeps_char_boundary() {
+        let mut history = History { entries: Vec::new(), unsaved_idx: 0, max_size: 10, file_lines: HashMap::new(), pending: false };
+        let command = format!("{}é", "a".repeat(MAX_COMMAND_LEN - 1));
+        add_internal(&mut history, &command, None, None);
+        assert_eq!(history.entries[0].command, format!("{}...", "a".repeat(MAX_COMMAND_LEN - 1)));
+    }
+
+    #[test]
+    fn test_remove_pending_only_removes_a_recorded_line() {
+        let mut history = History { entries: Vec::new(), unsaved_idx: 0, max_size: 10, file_lines: HashMap::new(), pending: false };
+        add_internal(&mut history, "echo keep", None, None);
+        remove_pending(&mut history); // the current line was not recorded
+        assert_eq!(history.entries.len(), 1);
+
+        history.pending = add_internal(&mut history, "history -s x", None, None);
+        remove_pending(&mut history);
+        assert_eq!(history.entries.iter().map(|e| e.command.as_str()).collect::<Vec<_>>(), ["echo keep"]);
+        assert!(!history.pending);
+    }
 }
@@ -0,0 +1,199 @@
+//! z-style directory jumping: every directory the shell changes to is scored by frecency
+//! (how often and how recently it was visited) in `$JUMPFILE` (default `~/.craft_shell_jumps`),
+//! and `j pattern` goes to the best match. The file uses z's `path|rank|time` lines.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustyline::completion::Pair;
+
+use crate::{history, sys};
+
+/// When the ranks add up to more than this, they are all aged (scaled down) and the least used dropped.
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub rank: f64,
+    /// Last visit, seconds since the epoch.
+    pub time: i64,
+}
+
+impl Entry {
+    /// z's frecency: the rank weighted by how long ago the last visit was.
+    pub fn frecency(&self, now: i64) -> f64 {
+        match now - self.time {
+            ..3600 => self.rank * 4.0,
+            3600..86400 => self.rank * 2.0,
+            86400..604800 => self.rank / 2.0,
+            _ => self.rank / 4.0,
+        }
+    }
+}
+
+fn data_file() -> Option<PathBuf> {
+    match env::var_os("JUMPFILE") {
+        Some(file) if !file.is_empty() => Some(PathBuf::from(file)),
+        _ => env::var_os("HOME").map(|home| Path::new(&home).join(".craft_shell_jumps")),
+    }
+}
+
+fn parse(content: &str) -> Vec<Entry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            // the path may itself contain `|`, so split from the right
+            let mut fields = line.rsplitn(3, '|');
+            let time = fields.next()?.parse().ok()?;
+            let rank = fields.next()?.parse().ok()?;
+            Some(Entry { path: PathBuf::from(fields.next()?), rank, time })
+        })
+        .collect()
+}
+
+/// All recorded directories.
+pub fn entries() -> Vec<Entry> {
+    data_file().and_then(|file| fs::read_to_string(file).ok()).map(|c| parse(&c)).unwrap_or_default()
+}
+
+/// Adds a visit to `dir`. Errors are ignored: jumping is a convenience.
+pub fn record(dir: &Path) {
+    let Some(file) = data_file() else {
+        return;
+    };
+    if env::var_os("HOME").is_some_and(|home| dir == Path::new(&home)) {
+        return; // `cd` alone is not worth learning
+    }
+    let Ok(_lock) = history::lock_file(&file) else {
+        return;
+    };
+    let mut entries = fs::read_to_string(&file).map(|c| parse(&c)).unwrap_or_default();
+    add_visit(&mut entries, dir, sys::now());
+    let content: String = entries
+        .iter()
+        .map(|e| format!("{}|{}|{}\n", e.path.display(), e.rank, e.time))
+        .collect();
+    _ = history::replace_file(&file, &content);
+}
+
+fn add_visit(entries: &mut Vec<Entry>, dir: &Path, now: i64) {
+    match entries.iter_mut().find(|e| e.path == dir) {
+        Some(entry) => {
+            entry.rank += 1.0;
+            entry.time = now;
+        }
+        None => entries.push(Entry { path: dir.to_path_buf(), rank: 1.0, time: now }),
+    }
+    if entries.iter().map(|e| e.rank).sum::<f64>() > MAX_TOTAL_RANK {
+        for entry in entries.iter_mut() {
+            entry.rank *= 0.99;
+        }
+        entries.retain(|e| e.rank >= 1.0);
+    }
+}
+
+/// The existing directories whose path contains all `words` in order (case-insensitive unless a word
+/// has an uppercase letter), best first. A match whose last component contains the last word ranks first.
+pub fn matches(words: &[String], entries: &[Entry], now: i64) -> Vec<Entry> {
+    let mut found: Vec<(bool, f64, &Entry)> = entries
+        .iter()
+        .filter(|e| e.path.is_dir())
+        .filter_map(|e| {
+            let path = e.path.to_string_lossy();
+            let mut rest = path.as_ref();
+            for word in words {
+                rest = &rest[find_end(rest, word)?..];
+            }
+            let in_last = words.last().is_none_or(|last| {
+                e.path.file_name().is_some_and(|name| find_end(&name.to_string_lossy(), last).is_some())
+            });
+            Some((in_last, e.frecency(now), e))
+        })
+        .collect();
+    found.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.total_cmp(&a.1)));
+    found.into_iter().map(|(_, _, e)| e.clone()).collect()
+}
+
+/// Completion for `j`: the full paths of the directories matching `word`, best first.
+pub fn complete(word: &str) -> Vec<Pair> {
+    let words: Vec<String> = Some(word.to_string()).filter(|w| !w.is_empty()).into_iter().collect();
+    matches(&words, &entries(), sys::now())
+        .into_iter()
+        .map(|e| {
+            let path = e.path.display().to_string();
+            Pair { display: path.clone(), replacement: path }
+        })
+        .collect()
+}
+
+/// Where the first match of `needle` in `haystack` ends: case-insensitive unless `needle` has an uppercase
+/// letter. Compared char by char, so the offset is a char boundary of `haystack` even where lowercasing
+/// changes a char's length.
+fn find_end(haystack: &str, needle: &str) -> Option<usize> {
+    if needle.chars().any(char::is_uppercase) {
+        return haystack.find(needle).map(|at| at + needle.len());
+    }
+    let mut starts = haystack.char_indices().map(|(i, _)| i).chain([haystack.len()]);
+    starts.find_map(|start| {
+        let mut rest = haystack[start..].char_indices();
+        let mut end = start;
+        for n in needle.chars() {
+            let (i, h) = rest.next()?;
+            if !h.to_lowercase().eq(n.to_lowercase()) {
+                return None;
+            }
+            end = start + i + h.len_utf8();
+        }
+        Some(end)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_visit() {
+        let mut entries = parse("/a|b|2.5|100\n/c|1|200\nbroken\n");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, PathBuf::from("/a|b"));
+        add_visit(&mut entries, Path::new("/c"), 300);
+        assert_eq!(entries[1], Entry { path: PathBuf::from("/c"), rank: 2.0, time: 300 });
+        add_visit(&mut entries, Path::new("/d"), 300);
+        assert_eq!(entries.len(), 3);
+    }
+
+    #[test]
+    fn test_frecency() {
+        let entry = Entry { path: PathBuf::from("/"), rank: 8.0, time: 0 };
+        assert_eq!(entry.frecency(10), 32.0);
+        assert_eq!(entry.frecency(2 * 86400), 4.0);
+        assert_eq!(entry.frecency(30 * 86400), 2.0);
+    }
+
+    #[test]
+    fn test_matches() {
+        let entries = [
+            Entry { path: PathBuf::from("/usr/lib"), rank: 1.0, time: 0 },
+            Entry { path: PathBuf::from("/usr/local/lib"), rank: 10.0, time: 0 },
+            Entry { path: PathBuf::from("/lib/usr"), rank: 100.0, time: 0 },
+            Entry { path: PathBuf::from("/nonexistent/usr/lib"), rank: 100.0, time: 0 },
+        ];
+        let words = ["usr".to_string(), "lib".to_string()];
+        let found: Vec<PathBuf> = matches(&words, &entries, 0).into_iter().map(|e| e.path).collect();
+        assert_eq!(found, [PathBuf::from("/usr/local/lib"), PathBuf::from("/usr/lib")]);
+    }
+
+    #[test]
+    fn test_find_end() {
+        assert_eq!(find_end("/usr/Local", "local"), Some(10));
+        assert_eq!(find_end("/usr/local", "Local"), None);
+        assert_eq!(find_end("/usr", ""), Some(0));
+        // `Ⱥ` grows from 2 to 3 bytes when lowercased: offsets must still be those of the original
+        assert_eq!(find_end("/ȺȺ/a", "a"), Some(7));
+        assert_eq!(find_end("/ȺȺ/a", "ⱥⱥ"), Some(5));
+        assert_eq!(find_end("/ȺȺ/a", "/b"), None);
+    }
+}
@@ -1,28 +1,52 @@
-use crate::rline::ShellHelper;
+use crate::history::SharedHistory;
+use crate::rline::{FuzzyHistorySearch, HintAcceptor, ShellHelper, ViModeTracker};
 use rustyline::config::BellStyle;
-use rustyline::history::DefaultHistory;
-use rustyline::{Config, CompletionType, EditMode};
+use rustyline::{Config, CompletionType, EditMode, Event, EventHandler, KeyCode, KeyEvent, Modifiers};
 
 pub mod parse;
+pub mod pattern;
 pub mod rline;
 pub mod builtins;
+pub mod conditional;
+pub mod completion;
+pub mod dirs;
 pub mod executables;
 pub mod external;
+pub mod fields;
+pub mod fuzzy;
+pub mod highlight;
+pub mod histdb;
+pub mod histexpand;
 pub mod history;
+pub mod jump;
 pub mod pipeline;
+pub mod printf;
+pub mod regex;
+pub mod prompt;
+pub mod state;
+pub mod sys;
 
 
-pub fn create_editor(h: ShellHelper) -> rustyline::Result<rustyline::Editor<ShellHelper, DefaultHistory>> {
+pub fn create_editor(h: ShellHelper) -> rustyline::Result<rustyline::Editor<ShellHelper, SharedHistory>> {
     let config = Config::builder()
         .completion_type(CompletionType::List) // default: Emacs-style, cycles through candidates
         .bell_style(BellStyle::Audible)
-        .edit_mode(EditMode::Emacs) // e.g. Ctrl-A, Home - Move cursor to the beginning of line
+        .edit_mode(edit_mode()) // Emacs e.g. Ctrl-A, Home - Move cursor to the beginning of line
         .max_history_size(1000)
         .unwrap()
         .completion_prompt_limit(200) // trigger alert when completion is too ambiguous
         .build();
 
-    let mut rl = rustyline::Editor::<ShellHelper, DefaultHistory>::with_config(config)?;
+    let mut rl = rustyline::Editor::with_history(config, SharedHistory)?;
     rl.set_helper(Some(h));
+    rl.bind_sequence(Event::Any, EventHandler::Conditional(Box::new(ViModeTracker)));
+    rl.bind_sequence(KeyEvent(KeyCode::End, Modifiers::NONE), EventHandler::Conditional(Box::new(HintAcceptor)));
+    rl.bind_sequence(KeyEvent::alt('f'), EventHandler::Conditional(Box::new(HintAcceptor)));
+    rl.bind_sequence(KeyEvent::ctrl('r'), EventHandler::Conditional(Box::new(FuzzyHistorySearch)));
     Ok(rl)
 }
+
+/// The edit mode selected by `set -o vi` / `set -o emacs`.
+pub fn edit_mode() -> EditMode {
+    if state::vi_mode() { EditMode::Vi } else { EditMode::Emacs }
+}
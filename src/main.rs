@@ -1,71 +1,93 @@
 use std::env;
 use std::io::{self};
 use std::path::Path;
+use std::time::Instant;
+
+use rustyline::config::Configurer;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
 
 use shlib::{
-    builtins, external, history, pipeline,
-    parse::{parse, Command},
-    executables::{find_executable_in_path, get_all_executables},
-    rline::ShellHelper,
+    builtins, conditional, dirs, external, histexpand, history, pipeline, prompt, state,
+    history::SharedHistory,
+    parse::{is_incomplete, parse, Command},
+    executables::{find_executable_to_run, get_all_executables},
+    rline::{ShellHelper, VI_INSERT_INDICATOR},
 };
 
 fn main() {
-    let system_commands = get_all_executables(); // Scan PATH once
-    let h = ShellHelper { builtins: builtins::all().clone(), system_commands };
+    let h = ShellHelper { builtins: builtins::all().clone(), executables: Default::default(), prompt: Default::default() };
     let mut rl = shlib::create_editor(h).unwrap();
+    dirs::init();
 
     if let Ok(histfile) = env::var("HISTFILE") {
-        let path = Path::new(&histfile);
-        if let Ok(count) = history::read_from_file(path) {
-            let recent = history::get_recent(count);
-            for cmd in recent {
-                _ = rl.add_history_entry(cmd.as_str());
-            }
-        }
+        _ = history::read_from_file(Path::new(&histfile)); // rustyline navigates `history` directly
     }
 
     loop {
-        // Prompt
-        match rl.readline("$ ") {
-            Ok(line) => {
-                let cmd_line = line.trim();
-                if cmd_line.is_empty() { continue; }
+        // `set -o vi|emacs` may have changed the mode since the last prompt
+        rl.set_edit_mode(shlib::edit_mode());
 
-                _ = rl.add_history_entry(cmd_line);
-                history::add(cmd_line);
+        if let Ok(prompt_command) = env::var("PROMPT_COMMAND")
+            && !prompt_command.trim().is_empty()
+        {
+            let status = state::last_status(); // `\?` in PS1 should show the user's command status
+            if !run(prompt_command.trim()) {
+                break
+            }
+            state::set_last_status(status);
+        }
 
-                match parse(cmd_line) {
-                    Command::SimpleCommand(cmd, args) => {
-                        if cmd == builtins::CMD_EXIT {
-                            if let Ok(histfile) = env::var("HISTFILE") {
-                                _ = history::append_to_file(Path::new(&histfile));
+        if state::share_history()
+            && let Ok(histfile) = env::var("HISTFILE")
+        {
+            _ = history::sync_with_file(Path::new(&histfile));
+        }
+
+        match read_line(&mut rl, false) {
+            Ok(mut line) => {
+                // an incomplete command (`echo "abc`, `ls |`, `make &&`) goes on on `PS2` lines
+                while is_incomplete(&line) {
+                    match read_line(&mut rl, true) {
+                        Ok(more) => {
+                            line.push('\n');
+                            line.push_str(&more);
+                        }
+                        Err(e) => {
+                            if matches!(e, ReadlineError::Eof) {
+                                eprintln!("syntax error: unexpected end of file");
                             }
-                            break
+                            line.clear();
+                            break;
                         }
+                    }
+                }
+                let cmd_line = line.trim_end(); // leading blanks matter to HISTCONTROL=ignorespace
+                if cmd_line.trim_start().is_empty() { continue; }
 
-                        let mut stdout = io::stdout();
-                        let mut stderr = io::stderr();
-                        if cmd == builtins::CMD_HISTORY {
-                            _ = builtins::run_builtin(&*cmd, &args, &mut stdout, &mut stderr);
-                            // TODO cmd == "history -r .." -> update rustyline history
-                        } else if builtins::all().contains(&&*cmd) {
-                            _ = builtins::run_builtin(&*cmd, &args, &mut stdout, &mut stderr);
-                        } else if let Some(exec_path) = find_executable_in_path(&cmd) {
-                            _ = external::run_unix(exec_path, &cmd, &args);
-                        } else {
-                            eprintln!("{cmd}: command not found")
-                        }
-                    },
-                    Command::PipeCommand(commands) => {
-                        pipeline::run_pipeline(&commands);
-                    },
-                    c @ Command::RedirectCommand(_, _, _) => {
-                        pipeline::run_pipeline(&vec![c]);
-                    },
-                    Command::InvalidCommand(err) => {
-                        eprintln!("Error: {}", err);
+                let expansion = match histexpand::expand(cmd_line, &history::get_recent(history::len())) {
+                    Ok(expansion) => expansion,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        continue;
                     }
+                };
+                let cmd_line = expansion.line.as_str();
+                if expansion.expanded {
+                    eprintln!("{cmd_line}"); // bash echoes the expanded line
                 }
+
+                history::add(cmd_line);
+                if expansion.print_only {
+                    continue;
+                }
+                state::count_command();
+
+                let started = Instant::now();
+                if !run(cmd_line) {
+                    break
+                }
+                history::finish(state::last_status(), started.elapsed());
             },
             Err(_) => {
                 if let Ok(histfile) = env::var("HISTFILE") {
@@ -76,3 +98,80 @@ fn main() {
         }
     }
 }
+
+/// Reads a line with the `PS1` prompt, or with `PS2` when it continues an incomplete command.
+fn read_line(rl: &mut Editor<ShellHelper, SharedHistory>, continuation: bool) -> rustyline::Result<String> {
+    state::set_vi_command(false); // every line starts in insert mode
+    let mut prompt = prompt::current(continuation);
+    if state::vi_mode() {
+        prompt.prepend(VI_INSERT_INDICATOR);
+    }
+    let plain_prompt = prompt.plain.clone();
+    if let Some(h) = rl.helper_mut() {
+        h.prompt = prompt;
+        if !continuation {
+            h.executables = get_all_executables();
+        }
+    }
+    rl.readline(&plain_prompt)
+}
+
+/// Executes one command line and records its exit status.
+/// Returns `false` when the shell should exit.
+fn run(cmd_line: &str) -> bool {
+    match execute(parse(cmd_line)) {
+        Some(status) => {
+            state::set_last_status(status);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the exit status, or `None` for `exit`.
+fn execute(command: Command) -> Option<i32> {
+    let status = match builtins::bypass(command) {
+        Command::SimpleCommand(cmd, args) => {
+            if cmd == builtins::CMD_EXIT {
+                if let Ok(histfile) = env::var("HISTFILE") {
+                    _ = history::append_to_file(Path::new(&histfile));
+                }
+                return None
+            }
+
+            let mut stdout = io::stdout();
+            let mut stderr = io::stderr();
+            if builtins::all().contains(&&*cmd) {
+                match builtins::run_builtin(&cmd, &args, &mut io::stdin(), &mut stdout, &mut stderr) {
+                    Some(Ok(status)) => status,
+                    _ => 1,
+                }
+            } else if let Some(exec_path) = find_executable_to_run(&cmd) {
+                external::run_unix(exec_path, &cmd, &args).unwrap_or(126)
+            } else {
+                eprintln!("{cmd}: command not found");
+                127
+            }
+        },
+        Command::PipeCommand(commands) => {
+            pipeline::run_pipeline(&commands)
+        },
+        c @ Command::RedirectCommand(_, _, _) => {
+            pipeline::run_pipeline(&[c])
+        },
+        Command::AndCommand(left, right) => {
+            let status = execute(*left)?;
+            if status == 0 { execute(*right)? } else { status }
+        },
+        Command::OrCommand(left, right) => {
+            let status = execute(*left)?;
+            if status != 0 { execute(*right)? } else { status }
+        },
+        Command::ConditionalCommand(expr) => conditional::run(&expr, &mut io::stderr()),
+        Command::InvalidCommand(err) => {
+            eprintln!("Error: {}", err);
+            2
+        }
+    };
+    Some(status)
+}
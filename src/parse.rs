@@ -1,3 +1,5 @@
+use crate::conditional;
+
 #[derive(Debug, PartialEq)]
 pub enum RedirectKind {
     Stdout,
@@ -13,15 +15,108 @@ pub enum Command {
     SimpleCommand(String, Vec<String>),
     PipeCommand(Vec<Command>),
     RedirectCommand(Box<Command>, String, RedirectKind),
+    /// `left && right`
+    AndCommand(Box<Command>, Box<Command>),
+    /// `left || right`
+    OrCommand(Box<Command>, Box<Command>),
+    /// `[[ expression ]]`
+    ConditionalCommand(ConditionalExpr),
     InvalidCommand(String),
 }
 
+/// The expression of a `[[ ]]` command. Words are kept whole: nothing inside `[[ ]]` is split.
+#[derive(Debug, PartialEq)]
+pub enum ConditionalExpr {
+    Not(Box<ConditionalExpr>),
+    And(Box<ConditionalExpr>, Box<ConditionalExpr>),
+    Or(Box<ConditionalExpr>, Box<ConditionalExpr>),
+    /// `-f file`, `-n string`, ...
+    Unary(String, String),
+    /// `left op right`. The right side of `==`, `=` and `!=` is a glob pattern and that of `=~` a regex,
+    /// with their quoted characters escaped by a backslash so that they match literally.
+    Binary(String, String, String),
+    /// A lone word, true if not empty.
+    Word(String),
+}
+
 pub fn parse(s: &str) -> Command {
     let s = s.trim();
     if s.is_empty() {
         return Command::InvalidCommand("Empty command".to_string());
     }
 
+    let parts = match split_by_and_or(s) {
+        Ok(p) => p,
+        Err(e) => return Command::InvalidCommand(e),
+    };
+
+    // `a && b || c` groups as `(a && b) || c`
+    let mut parts = parts.into_iter();
+    let (first, mut connector) = parts.next().unwrap();
+    let mut command = parse_pipeline(&first);
+    for (part, next_connector) in parts {
+        if let Command::InvalidCommand(_) = command {
+            return command;
+        }
+        let right = parse_pipeline(&part);
+        if let Command::InvalidCommand(_) = right {
+            return right;
+        }
+        command = match connector {
+            Some(Connector::And) => Command::AndCommand(Box::new(command), Box::new(right)),
+            _ => Command::OrCommand(Box::new(command), Box::new(right)),
+        };
+        connector = next_connector;
+    }
+    command
+}
+
+/// Tells whether `s` needs more input before it can be run: an unclosed quote,
+/// a trailing backslash, or a trailing `|`, `&&` or `||`.
+/// The main loop reads continuation lines with the `PS2` prompt until it is complete.
+pub fn is_incomplete(s: &str) -> bool {
+    let mut in_single_quote = false;
+    let mut in_double_quote = false;
+    let mut escaped = false;
+    let mut last = ' '; // last non-blank character outside quotes
+    let mut before_last = ' ';
+
+    for c in s.chars() {
+        if escaped {
+            escaped = false;
+            (before_last, last) = (' ', 'x');
+            continue;
+        }
+        match c {
+            '\\' if !in_single_quote => escaped = true,
+            '\'' if !in_double_quote => in_single_quote = !in_single_quote,
+            '"' if !in_single_quote => in_double_quote = !in_double_quote,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            (before_last, last) = (last, c);
+        }
+    }
+
+    escaped
+        || in_single_quote
+        || in_double_quote
+        || last == '|'
+        || (last == '&' && before_last == '&')
+}
+
+#[derive(Debug, PartialEq)]
+enum Connector {
+    And,
+    Or,
+}
+
+fn parse_pipeline(s: &str) -> Command {
+    let s = s.trim();
+    if s.is_empty() {
+        return Command::InvalidCommand("Empty command".to_string());
+    }
+
     let parts = match split_by_pipe(s) {
         Ok(p) => p,
         Err(e) => return Command::InvalidCommand(e),
@@ -57,42 +152,21 @@ fn parse_simple(s: &str) -> Command {
         // This might happen if s was just whitespace
         return Command::InvalidCommand("Empty command".to_string());
     }
+    if tokens[0] == "[[" && s.trim_start().starts_with("[[") {
+        return parse_conditional(s); // the keyword, not a quoted `[[`
+    }
 
     let mut args = Vec::new();
     let mut redirects = Vec::new();
     let mut tokens_iter = tokens.into_iter();
 
     while let Some(token) = tokens_iter.next() {
-        match token.as_str() {
-            ">" | "1>" => {
-                if let Some(path) = tokens_iter.next() {
-                    redirects.push((path, RedirectKind::Stdout));
-                } else {
-                    return Command::InvalidCommand("Missing path for redirect".to_string());
-                }
-            }
-            ">>" | "1>>" => {
-                if let Some(path) = tokens_iter.next() {
-                    redirects.push((path, RedirectKind::StdoutAppend));
-                } else {
-                    return Command::InvalidCommand("Missing path for redirect".to_string());
-                }
-            }
-            "2>" => {
-                if let Some(path) = tokens_iter.next() {
-                    redirects.push((path, RedirectKind::Stderr));
-                } else {
-                    return Command::InvalidCommand("Missing path for redirect".to_string());
-                }
-            }
-            "2>>" => {
-                if let Some(path) = tokens_iter.next() {
-                    redirects.push((path, RedirectKind::StderrAppend));
-                } else {
-                    return Command::InvalidCommand("Missing path for redirect".to_string());
-                }
-            }
-            _ => args.push(token),
+        match redirect_kind(&token) {
+            Some(kind) => match tokens_iter.next() {
+                Some(path) => redirects.push((path, kind)),
+                None => return Command::InvalidCommand("Missing path for redirect".to_string()),
+            },
+            None => args.push(token),
         }
     }
 
@@ -110,19 +184,94 @@ fn parse_simple(s: &str) -> Command {
     command
 }
 
+/// The redirection a word stands for, if it is one of the operators (always a separate word).
+pub(crate) fn redirect_kind(operator: &str) -> Option<RedirectKind> {
+    Some(match operator {
+        ">" | "1>" => RedirectKind::Stdout,
+        ">>" | "1>>" => RedirectKind::StdoutAppend,
+        "2>" => RedirectKind::Stderr,
+        "2>>" => RedirectKind::StderrAppend,
+        _ => return None,
+    })
+}
+
+/// Splits at `&&` and `||` outside quotes; each part carries the connector that follows it.
+fn split_by_and_or(s: &str) -> Result<Vec<(String, Option<Connector>)>, String> {
+    let mut parts = Vec::new();
+    let mut current_part = String::new();
+    let mut in_single_quote = false;
+    let mut in_double_quote = false;
+    let mut escaped = false;
+    let mut in_conditional = false;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if escaped {
+            current_part.push(c);
+            escaped = false;
+            continue;
+        }
+        if !in_single_quote && !in_double_quote {
+            in_conditional = conditional_state(in_conditional, &current_part, &s[i..]);
+        }
+
+        match c {
+            '\\' if !in_single_quote => {
+                escaped = true;
+                current_part.push(c);
+            }
+            '\'' if !in_double_quote => {
+                in_single_quote = !in_single_quote;
+                current_part.push(c);
+            }
+            '"' if !in_single_quote => {
+                in_double_quote = !in_double_quote;
+                current_part.push(c);
+            }
+            '&' | '|' if !in_single_quote && !in_double_quote && !in_conditional
+                && chars.peek().is_some_and(|&(_, n)| n == c) => {
+                chars.next();
+                if current_part.trim().is_empty() {
+                    return Err(format!("syntax error near unexpected token `{c}{c}'"));
+                }
+                let connector = if c == '&' { Connector::And } else { Connector::Or };
+                parts.push((std::mem::take(&mut current_part), Some(connector)));
+            }
+            _ => current_part.push(c),
+        }
+    }
+
+    if escaped {
+        return Err("Trailing backslash".to_string());
+    }
+    if in_single_quote || in_double_quote {
+        return Err("Unpaired quote".to_string());
+    }
+    if current_part.trim().is_empty() && !parts.is_empty() {
+        return Err("syntax error: unexpected end of file".to_string());
+    }
+
+    parts.push((current_part, None));
+    Ok(parts)
+}
+
 fn split_by_pipe(s: &str) -> Result<Vec<String>, String> {
     let mut parts = Vec::new();
     let mut current_part = String::new();
     let mut in_single_quote = false;
     let mut in_double_quote = false;
     let mut escaped = false;
+    let mut in_conditional = false;
 
-    for c in s.chars() {
+    for (i, c) in s.char_indices() {
         if escaped {
             current_part.push(c);
             escaped = false;
             continue;
         }
+        if !in_single_quote && !in_double_quote {
+            in_conditional = conditional_state(in_conditional, &current_part, &s[i..]);
+        }
 
         match c {
             '\\' => {
@@ -145,13 +294,9 @@ fn split_by_pipe(s: &str) -> Result<Vec<String>, String> {
                 }
                 current_part.push(c);
             }
-            '|' => {
-                if !in_single_quote && !in_double_quote {
-                    parts.push(current_part);
-                    current_part = String::new();
-                } else {
-                    current_part.push(c);
-                }
+            '|' if !in_single_quote && !in_double_quote && !in_conditional => {
+                parts.push(current_part);
+                current_part = String::new();
             }
             _ => {
                 current_part.push(c);
@@ -170,16 +315,255 @@ fn split_by_pipe(s: &str) -> Result<Vec<String>, String> {
     Ok(parts)
 }
 
-fn tokenize(s: &str) -> Result<Vec<String>, String> {
+/// Whether the splitters are inside `[[ ]]` at the start of `rest`, `before` being the text of the current
+/// command so far: `&&`, `||` and `|` between `[[` and `]]` belong to the conditional expression.
+/// `[[` only counts as the command name, and both only as whole words.
+fn conditional_state(in_conditional: bool, before: &str, rest: &str) -> bool {
+    let word_ends = |rest: &str| rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c == '|' || c == '&');
+    if in_conditional {
+        !(before.ends_with(char::is_whitespace) && rest.strip_prefix("]]").is_some_and(word_ends))
+    } else {
+        let before = before.trim_end();
+        (before.is_empty() || before.ends_with('|'))
+            && rest.strip_prefix("[[").is_some_and(|r| r.starts_with(char::is_whitespace))
+    }
+}
+
+/// Parses `[[ expression ]]`. `&&`, `||`, `!`, `(`, `)`, `<` and `>` in it belong to the expression
+/// rather than separating commands or redirecting, and must be separate words (except in a regex).
+fn parse_conditional(s: &str) -> Command {
+    let lexed = lex(s);
+    let tokens = &lexed.tokens[1..];
+    let Some(close) = tokens.iter().position(|t| t.kind == TokenKind::Word && &s[t.start..t.end] == "]]") else {
+        return Command::InvalidCommand("syntax error: unexpected end of file, expected `]]'".to_string());
+    };
+    // only redirections may follow `]]`
+    let mut redirects = Vec::new();
+    let mut rest = tokens[close + 1..].iter();
+    while let Some(t) = rest.next() {
+        let Some(kind) = redirect_kind(&s[t.start..t.end]) else {
+            return Command::InvalidCommand(format!("syntax error near unexpected token `{}'", t.text));
+        };
+        match rest.next() {
+            Some(path) => redirects.push((path.text.clone(), kind)),
+            None => return Command::InvalidCommand("Missing path for redirect".to_string()),
+        }
+    }
+    let mut parser = ConditionalParser { s, tokens: &tokens[..close], pos: 0 };
+    let mut command = match parser.or() {
+        Ok(expr) => match parser.tokens.get(parser.pos) {
+            None => Command::ConditionalCommand(expr),
+            Some(t) => return Command::InvalidCommand(format!("syntax error in conditional expression near `{}'", t.text)),
+        },
+        Err(e) => return Command::InvalidCommand(e),
+    };
+    for (path, kind) in redirects {
+        command = Command::RedirectCommand(Box::new(command), path, kind);
+    }
+    command
+}
+
+struct ConditionalParser<'a> {
+    s: &'a str,
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> ConditionalParser<'a> {
+    fn raw(&self, offset: usize) -> Option<&'a str> {
+        self.tokens.get(self.pos + offset).map(|t| &self.s[t.start..t.end])
+    }
+
+    /// The next token, which must be a word.
+    fn word(&mut self) -> Result<&'a Token, String> {
+        match self.tokens.get(self.pos) {
+            Some(t) if t.kind == TokenKind::Word => {
+                self.pos += 1;
+                Ok(t)
+            }
+            Some(t) => Err(format!("syntax error in conditional expression near `{}'", t.text)),
+            None => Err("syntax error in conditional expression: unexpected `]]'".to_string()),
+        }
+    }
+
+    fn or(&mut self) -> Result<ConditionalExpr, String> {
+        let mut expr = self.and()?;
+        while self.raw(0) == Some("||") {
+            self.pos += 1;
+            expr = ConditionalExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<ConditionalExpr, String> {
+        let mut expr = self.not()?;
+        while self.raw(0) == Some("&&") {
+            self.pos += 1;
+            expr = ConditionalExpr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<ConditionalExpr, String> {
+        if self.raw(0) == Some("!") {
+            self.pos += 1;
+            return Ok(ConditionalExpr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<ConditionalExpr, String> {
+        if self.raw(0) == Some("(") {
+            self.pos += 1;
+            let expr = self.or()?;
+            if self.raw(0) != Some(")") {
+                return Err("syntax error in conditional expression: expected `)'".to_string());
+            }
+            self.pos += 1;
+            return Ok(expr);
+        }
+        let is_word = |t: Option<&Token>| t.is_some_and(|t| t.kind == TokenKind::Word);
+        let operator = self.raw(0).unwrap_or_default().to_string();
+        if conditional::UNARY_OPERATORS.contains(&&*operator) && is_word(self.tokens.get(self.pos + 1)) {
+            self.pos += 1;
+            return Ok(ConditionalExpr::Unary(operator, self.word()?.text.clone()));
+        }
+        let left = self.word()?.text.clone();
+        let Some(operator) = self.raw(0).map(str::to_string) else {
+            return Ok(ConditionalExpr::Word(left));
+        };
+        let right = match operator.as_str() {
+            "=~" => {
+                self.pos += 1;
+                self.regex()?
+            }
+            "==" | "=" | "!=" => {
+                self.pos += 1;
+                let t = self.word()?;
+                literal_quoted(&self.s[t.start..t.end], "*?[\\")
+            }
+            op if conditional::BINARY_OPERATORS.contains(&op) => {
+                self.pos += 1;
+                self.word()?.text.clone()
+            }
+            _ => return Ok(ConditionalExpr::Word(left)),
+        };
+        Ok(ConditionalExpr::Binary(left, operator, right))
+    }
+
+    /// The right side of `=~`: the next word, joined with the `|` operators and words that directly touch it,
+    /// since `|` is alternation in a regex.
+    fn regex(&mut self) -> Result<String, String> {
+        let first = self.word()?;
+        let mut end = first.end;
+        let mut regex = literal_quoted(&self.s[first.start..first.end], REGEX_SPECIAL);
+        while let Some(t) = self.tokens.get(self.pos)
+            && t.start == end
+            && (t.kind == TokenKind::Word || t.text != "&&")
+        {
+            regex.push_str(&literal_quoted(&self.s[t.start..t.end], REGEX_SPECIAL));
+            end = t.end;
+            self.pos += 1;
+        }
+        Ok(regex)
+    }
+}
+
+const REGEX_SPECIAL: &str = "\\.[]()*+?{}|^$";
+
+/// Removes the quotes of the raw word `raw`, escaping with a backslash each quoted character found in `special`,
+/// so that a pattern made from it matches those characters literally.
+fn literal_quoted(raw: &str, special: &str) -> String {
+    let quoted = |out: &mut String, c: char| {
+        if special.contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    };
+    let mut out = String::new();
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    quoted(&mut out, next);
+                }
+            }
+            '\'' => {
+                for next in chars.by_ref().take_while(|&n| n != '\'') {
+                    quoted(&mut out, next);
+                }
+            }
+            '"' => {
+                while let Some(next) = chars.next() {
+                    match next {
+                        '"' => break,
+                        '\\' if chars.peek().is_some_and(|n| "$`\"\\".contains(*n)) => {
+                            quoted(&mut out, chars.next().unwrap());
+                        }
+                        _ => quoted(&mut out, next),
+                    }
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Word,
+    /// `|`, `||` or `&&`
+    Operator,
+}
+
+/// A word (quotes and escapes already removed) or an operator,
+/// with the byte range it covers in the lexed input.
+#[derive(Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Result of `lex`: the tokens, plus the state at the end of the input,
+/// which is what completion needs to know about a partially typed word.
+#[derive(Debug, PartialEq)]
+pub struct Lexed {
+    pub tokens: Vec<Token>,
+    /// `Some('\'')` or `Some('"')` when the input ends inside quotes.
+    pub open_quote: Option<char>,
+    /// The input ends with an unescaped backslash.
+    pub escaped: bool,
+}
+
+/// Splits `s` into words and operators, following the same quoting rules as `parse`.
+/// Never fails: unterminated quotes or a trailing backslash are reported in `Lexed`.
+pub fn lex(s: &str) -> Lexed {
     let mut tokens = Vec::new();
     let mut current_token = String::new();
+    let mut token_start: Option<usize> = None;
     let mut in_single_quote = false;
     let mut in_double_quote = false;
     let mut escaped = false;
+    let mut chars = s.char_indices().peekable();
 
-    for c in s.chars() {
+    fn finish(tokens: &mut Vec<Token>, text: &mut String, start: &mut Option<usize>, end: usize) {
+        if let Some(start) = start.take() {
+            tokens.push(Token { kind: TokenKind::Word, text: std::mem::take(text), start, end });
+        }
+    }
+
+    while let Some((i, c)) = chars.next() {
         if escaped {
-            if in_double_quote {
+            if c == '\n' {
+                // line continuation: backslash-newline is removed
+                if current_token.is_empty() && token_start == Some(i - 1) {
+                    token_start = None;
+                }
+            } else if in_double_quote {
                 match c {
                     '$' | '`' | '"' | '\\' | '\n' => current_token.push(c),
                     _ => {
@@ -194,6 +578,28 @@ fn tokenize(s: &str) -> Result<Vec<String>, String> {
             continue;
         }
 
+        let quoted = in_single_quote || in_double_quote;
+        match c {
+            ' ' | '\t' | '\n' | '\r' if !quoted => {
+                finish(&mut tokens, &mut current_token, &mut token_start, i);
+                continue;
+            }
+            '|' | '&' if !quoted && (c == '|' || chars.peek().is_some_and(|&(_, n)| n == '&')) => {
+                finish(&mut tokens, &mut current_token, &mut token_start, i);
+                let mut end = i + 1;
+                if let Some(&(_, n)) = chars.peek()
+                    && n == c
+                {
+                    chars.next();
+                    end += 1;
+                }
+                tokens.push(Token { kind: TokenKind::Operator, text: s[i..end].to_string(), start: i, end });
+                continue;
+            }
+            _ => {}
+        }
+
+        token_start.get_or_insert(i);
         match c {
             '\\' => {
                 if in_single_quote {
@@ -216,32 +622,32 @@ fn tokenize(s: &str) -> Result<Vec<String>, String> {
                     in_double_quote = !in_double_quote;
                 }
             }
-            ' ' | '\t' | '\n' | '\r' => {
-                if in_single_quote || in_double_quote {
-                    current_token.push(c);
-                } else if !current_token.is_empty() {
-                    tokens.push(current_token);
-                    current_token = String::new();
-                }
-            }
             _ => {
                 current_token.push(c);
             }
         }
     }
+    finish(&mut tokens, &mut current_token, &mut token_start, s.len());
 
-    if escaped {
+    let open_quote = if in_single_quote {
+        Some('\'')
+    } else if in_double_quote {
+        Some('"')
+    } else {
+        None
+    };
+    Lexed { tokens, open_quote, escaped }
+}
+
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let lexed = lex(s);
+    if lexed.escaped {
         return Err("Trailing backslash".to_string());
     }
-    if in_single_quote || in_double_quote {
+    if lexed.open_quote.is_some() {
         return Err("Unpaired quote".to_string());
     }
-
-    if !current_token.is_empty() {
-        tokens.push(current_token);
-    }
-
-    Ok(tokens)
+    Ok(lexed.tokens.into_iter().map(|t| t.text).collect())
 }
 
 #[cfg(test)]
@@ -491,4 +897,120 @@ mod tests {
         );
         assert_eq!(parse(input), expected);
     }
+
+    #[test]
+    fn test_and_or_list() {
+        let input = "true && echo yes || echo no";
+        let expected = Command::OrCommand(
+            Box::new(Command::AndCommand(
+                Box::new(Command::SimpleCommand("true".to_string(), vec![])),
+                Box::new(Command::SimpleCommand("echo".to_string(), vec!["yes".to_string()])),
+            )),
+            Box::new(Command::SimpleCommand("echo".to_string(), vec!["no".to_string()])),
+        );
+        assert_eq!(parse(input), expected);
+    }
+
+    #[test]
+    fn test_and_with_pipe_and_quotes() {
+        let input = "echo 'a && b' | cat && ls";
+        let expected = Command::AndCommand(
+            Box::new(Command::PipeCommand(vec![
+                Command::SimpleCommand("echo".to_string(), vec!["a && b".to_string()]),
+                Command::SimpleCommand("cat".to_string(), vec![]),
+            ])),
+            Box::new(Command::SimpleCommand("ls".to_string(), vec![])),
+        );
+        assert_eq!(parse(input), expected);
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let input = "echo a \\\nb";
+        let expected = Command::SimpleCommand("echo".to_string(), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(parse(input), expected);
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("echo \"abc"));
+        assert!(is_incomplete("echo 'abc"));
+        assert!(is_incomplete("echo abc \\"));
+        assert!(is_incomplete("ls |"));
+        assert!(is_incomplete("ls &&  "));
+        assert!(is_incomplete("ls ||"));
+        assert!(!is_incomplete("echo \"abc\ndef\""));
+        assert!(!is_incomplete("echo '|'"));
+        assert!(!is_incomplete("echo \\|"));
+        assert!(!is_incomplete("ls | wc"));
+    }
+
+    #[test]
+    fn test_empty_quotes_are_an_argument() {
+        let input = "echo '' x";
+        let expected = Command::SimpleCommand("echo".to_string(), vec!["".to_string(), "x".to_string()]);
+        assert_eq!(parse(input), expected);
+    }
+
+    #[test]
+    fn test_lex_spans_and_open_quote() {
+        let lexed = lex("ls|cat 'my fi");
+        let spans: Vec<(TokenKind, &str, usize, usize)> = lexed.tokens.iter()
+            .map(|t| (t.kind, t.text.as_str(), t.start, t.end))
+            .collect();
+        assert_eq!(spans, vec![
+            (TokenKind::Word, "ls", 0, 2),
+            (TokenKind::Operator, "|", 2, 3),
+            (TokenKind::Word, "cat", 3, 6),
+            (TokenKind::Word, "my fi", 7, 13),
+        ]);
+        assert_eq!(lexed.open_quote, Some('\''));
+    }
+
+    #[test]
+    fn test_conditional() {
+        use ConditionalExpr::*;
+        let b = |l: &str, op: &str, r: &str| Binary(l.to_string(), op.to_string(), r.to_string());
+        let input = "[[ ! -d 'a b' && x == *.rs || ( \"y\" < z ) ]] && echo ok";
+        let expected = Command::AndCommand(
+            Box::new(Command::ConditionalCommand(Or(
+                Box::new(And(
+                    Box::new(Not(Box::new(Unary("-d".to_string(), "a b".to_string())))),
+                    Box::new(b("x", "==", "*.rs")),
+                )),
+                Box::new(b("y", "<", "z")),
+            ))),
+            Box::new(Command::SimpleCommand("echo".to_string(), vec!["ok".to_string()])),
+        );
+        assert_eq!(parse(input), expected);
+        assert_eq!(parse("[[ -n ]]"), Command::ConditionalCommand(Word("-n".to_string())));
+        assert!(matches!(parse("echo [[ a && b ]]"), Command::AndCommand(..)));
+        assert!(matches!(parse("[[ a == b"), Command::InvalidCommand(_)));
+        assert!(matches!(parse("[[ a b ]]"), Command::InvalidCommand(_)));
+        assert!(matches!(parse("[[ ]]"), Command::InvalidCommand(_)));
+        assert_eq!(parse("[[ -n x ]] 2> err > out"), Command::RedirectCommand(
+            Box::new(Command::RedirectCommand(
+                Box::new(Command::ConditionalCommand(Unary("-n".to_string(), "x".to_string()))),
+                "err".to_string(),
+                RedirectKind::Stderr,
+            )),
+            "out".to_string(),
+            RedirectKind::Stdout,
+        ));
+        assert!(matches!(parse("[[ -n x ]] '>' out"), Command::InvalidCommand(_)));
+        assert!(matches!(parse("[[ -n x ]] >"), Command::InvalidCommand(_)));
+    }
+
+    #[test]
+    fn test_conditional_quoted_patterns() {
+        let parsed = |s: &str| match parse(s) {
+            Command::ConditionalCommand(ConditionalExpr::Binary(_, _, right)) => right,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(parsed("[[ x == '*'.rs ]]"), "\\*.rs");
+        assert_eq!(parsed("[[ x == \"a?\"* ]]"), "a\\?*");
+        assert_eq!(parsed("[[ x =~ ^(a|b)'.'c$ ]]"), "^(a|b)\\.c$");
+        assert_eq!(parsed("[[ x =~ a\\|b ]]"), "a\\|b");
+        assert_eq!(parsed("[[ x =~ a|b ]]"), "a|b");
+    }
 }
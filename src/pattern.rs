@@ -0,0 +1,96 @@
+//! Shell glob patterns (`*`, `?`, `[...]`), matched against whole strings.
+
+/// Whether `s` matches the glob `pattern` as a whole.
+/// Supports `*`, `?`, bracket expressions (`[abc]`, `[a-z]`, `[!x]`/`[^x]`)
+/// and `\` to match the next character literally.
+pub fn matches(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut p, mut i) = (0, 0);
+    // where to resume after the last `*`: (pattern index after it, string index it matched up to)
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, i));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match bracket(&pattern[p..], s[i]) {
+                Some((matched, len)) => matched.then_some(p + len),
+                None => (s[i] == '[').then_some(p + 1), // unterminated: a literal `[`
+            },
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == s[i]).then_some(p + 2),
+            Some(&c) => (c == s[i]).then_some(p + 1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                i += 1;
+            }
+            (None, Some((star_p, star_i))) => {
+                // let the last `*` swallow one more character
+                backtrack = Some((star_p, star_i + 1));
+                p = star_p;
+                i = star_i + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the bracket expression at the start of `pattern`.
+/// Returns whether it matched and the length of the expression, or `None` if it isn't closed.
+fn bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut j = 1;
+    let negated = matches!(pattern.get(j), Some('!' | '^'));
+    if negated {
+        j += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let lo = *pattern.get(j)?;
+        if lo == ']' && !first {
+            return Some((matched != negated, j + 1));
+        }
+        first = false;
+        if pattern.get(j + 1) == Some(&'-') && pattern.get(j + 2).is_some_and(|&hi| hi != ']') {
+            matched |= (lo..=pattern[j + 2]).contains(&c);
+            j += 3;
+        } else {
+            matched |= lo == c;
+            j += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("ls*", "ls -la"));
+        assert!(matches("*", ""));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*.rs", "src/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("a*b*c", "axxbyyc"));
+    }
+
+    #[test]
+    fn test_brackets_and_escapes() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("[", "["));
+    }
+}
@@ -1,25 +1,85 @@
-use std::process::{Stdio, Child};
+use std::process::{Stdio, Child, ChildStdout};
 use std::thread;
 use std::fs::OpenOptions;
+use std::io::{self, Cursor, Write};
 
-use crate::executables::find_executable_in_path;
+use crate::executables::find_executable_to_run;
 use crate::parse::{Command, RedirectKind};
-use crate::builtins;
+use crate::builtins::{self, Input};
+use crate::conditional;
 use crate::external::prepare_unix_command;
 
-pub fn run_pipeline(commands: &[Command]) {
+/// What the next command of a pipeline reads.
+enum PipeInput {
+    /// The shell's own stdin.
+    Inherit,
+    /// The output of the previous, external command.
+    Child(ChildStdout),
+    /// The output of the previous builtin, which has already run.
+    Buffer(Vec<u8>),
+}
+
+impl PipeInput {
+    /// Stdin for an external command. A builtin's buffered output only ever feeds another builtin,
+    /// since a builtin followed by an external command writes into its stdin directly.
+    fn into_stdio(self) -> Stdio {
+        match self {
+            PipeInput::Inherit => Stdio::inherit(),
+            PipeInput::Child(stdout) => Stdio::from(stdout),
+            PipeInput::Buffer(_) => Stdio::null(),
+        }
+    }
+
+    /// Stdin for a builtin.
+    fn into_input(self) -> Box<dyn Input> {
+        match self {
+            PipeInput::Inherit => Box::new(io::stdin()),
+            PipeInput::Child(stdout) => Box::new(stdout),
+            PipeInput::Buffer(output) => Box::new(Cursor::new(output)),
+        }
+    }
+}
+
+/// Runs the commands connected by pipes and returns the exit status of the last one.
+pub fn run_pipeline(commands: &[Command]) -> i32 {
     if commands.is_empty() {
-        return;
+        return 0;
     }
 
     let mut children: Vec<Child> = Vec::new();
-    let mut input_source: Stdio = Stdio::inherit();
+    // Builtins writing into an external command's stdin; joined once the children are done.
+    let mut writers: Vec<thread::JoinHandle<()>> = Vec::new();
+    let mut status = 0;
+    let mut last_child: Option<usize> = None;
+    let mut input = PipeInput::Inherit;
     let mut i = 0;
 
     while i < commands.len() {
         let (cmd_ref, redirects) = unwrap_command(&commands[i]);
 
         let is_last = i == commands.len() - 1;
+        if let Command::ConditionalCommand(expr) = cmd_ref {
+            // `[[ ]]` writes nothing but its errors, so a stdout redirect only creates the file
+            // and the next command reads empty input
+            let mut stderr: Box<dyn Write> = Box::new(io::stderr());
+            for (path, kind) in redirects.iter().rev() {
+                match open_redirect_file(path, kind) {
+                    Ok(_) if matches!(kind, RedirectKind::Stdout | RedirectKind::StdoutAppend) => {}
+                    Ok(f) => stderr = Box::new(f),
+                    Err(_) => {
+                        eprintln!("Failed to open redirect file: {}", path);
+                        return 1;
+                    }
+                }
+            }
+            let result = conditional::run(expr, &mut stderr);
+            if is_last {
+                status = result;
+            }
+            input = PipeInput::Buffer(Vec::new());
+            i += 1;
+            continue;
+        }
         let is_builtin = match cmd_ref {
             Command::SimpleCommand(c, _) => builtins::all().contains(&c.as_str()),
             _ => false,
@@ -32,11 +92,11 @@ pub fn run_pipeline(commands: &[Command]) {
                 _ => { i += 1; continue; }
             };
 
-            let path = match find_executable_in_path(cmd_name) {
+            let path = match find_executable_to_run(cmd_name) {
                 Some(p) => p,
                 None => {
                     eprintln!("{}: command not found", cmd_name);
-                    return;
+                    return 127;
                 }
             };
 
@@ -57,34 +117,33 @@ pub fn run_pipeline(commands: &[Command]) {
                     }
                 } else {
                     eprintln!("Failed to open redirect file: {}", path);
-                    return;
+                    return 1;
                 }
             }
 
             match prepare_unix_command(&path, cmd_name, args)
-                .stdin(input_source)
+                .stdin(std::mem::replace(&mut input, PipeInput::Inherit).into_stdio())
                 .stdout(stdout_target)
                 .stderr(stderr_target)
                 .spawn()
             {
                 Ok(mut child) => {
                     if !is_last {
-                        input_source = Stdio::from(child.stdout.take().unwrap());
+                        input = PipeInput::Child(child.stdout.take().unwrap());
                     } else {
-                        input_source = Stdio::inherit();
+                        last_child = Some(children.len());
                     }
                     children.push(child);
                 },
                 Err(e) => {
                     eprintln!("Failed to start {}: {}", cmd_name, e);
-                    return;
+                    return 126;
                 }
             }
             i += 1;
         } else {
             // Builtin
-            // Builtins ignore stdin, so we close the previous pipe if any.
-            input_source = Stdio::inherit();
+            let mut stdin = std::mem::replace(&mut input, PipeInput::Inherit).into_input();
 
             let (cmd_name, args) = match cmd_ref {
                 Command::SimpleCommand(c, a) => (c, a),
@@ -109,7 +168,7 @@ pub fn run_pipeline(commands: &[Command]) {
                     }
                 } else {
                     eprintln!("Failed to open redirect file: {}", path);
-                    return;
+                    return 1;
                 }
             }
 
@@ -125,7 +184,10 @@ pub fn run_pipeline(commands: &[Command]) {
                     Box::new(std::io::stderr())
                 };
 
-                let _ = builtins::run_builtin(cmd_name, args, &mut stdout, &mut stderr);
+                status = match builtins::run_builtin(cmd_name, args, &mut *stdin, &mut stdout, &mut stderr) {
+                    Some(Ok(status)) => status,
+                    _ => 1,
+                };
                 i += 1;
             } else {
                 // Look ahead
@@ -138,11 +200,14 @@ pub fn run_pipeline(commands: &[Command]) {
 
                 if next_is_builtin {
                     // Builtin | Builtin
-                    // Run current to sink
-                    let mut stdout: Box<dyn std::io::Write> = if let Some(f) = stdout_redirect {
-                        Box::new(f)
+                    // Run current into a buffer the next one reads
+                    let mut output = Vec::new();
+                    let mut file;
+                    let stdout: &mut dyn Write = if let Some(f) = stdout_redirect {
+                        file = f;
+                        &mut file
                     } else {
-                        Box::new(std::io::sink())
+                        &mut output
                     };
                     let mut stderr: Box<dyn std::io::Write> = if let Some(f) = stderr_redirect {
                         Box::new(f)
@@ -150,7 +215,8 @@ pub fn run_pipeline(commands: &[Command]) {
                         Box::new(std::io::stderr())
                     };
 
-                    let _ = builtins::run_builtin(cmd_name, args, &mut stdout, &mut stderr);
+                    let _ = builtins::run_builtin(cmd_name, args, &mut *stdin, stdout, &mut stderr);
+                    input = PipeInput::Buffer(output);
 
                     i += 1;
                 } else {
@@ -161,11 +227,11 @@ pub fn run_pipeline(commands: &[Command]) {
                         _ => { i += 2; continue; }
                     };
 
-                    let next_path = match find_executable_in_path(next_name) {
+                    let next_path = match find_executable_to_run(next_name) {
                         Some(p) => p,
                         None => {
                             eprintln!("{}: command not found", next_name);
-                            return;
+                            return 127;
                         }
                     };
 
@@ -206,7 +272,7 @@ pub fn run_pipeline(commands: &[Command]) {
                                 let stdout_redirect = stdout_redirect;
                                 let stderr_redirect = stderr_redirect;
 
-                                thread::spawn(move || {
+                                writers.push(thread::spawn(move || {
                                     let mut stdout: Box<dyn std::io::Write> = if let Some(f) = stdout_redirect {
                                         Box::new(f)
                                     } else {
@@ -217,22 +283,22 @@ pub fn run_pipeline(commands: &[Command]) {
                                     } else {
                                         Box::new(std::io::stderr())
                                     };
-                                    let _ = builtins::run_builtin(&cmd_name_owned, &args_owned, &mut stdout, &mut stderr);
-                                });
+                                    let _ = builtins::run_builtin(&cmd_name_owned, &args_owned, &mut *stdin, &mut stdout, &mut stderr);
+                                }));
                             }
 
-                            // Update input_source for i+2
+                            // Update input for i+2
                             if !next_is_last {
-                                input_source = Stdio::from(child.stdout.take().unwrap());
+                                input = PipeInput::Child(child.stdout.take().unwrap());
                             } else {
-                                input_source = Stdio::inherit();
+                                last_child = Some(children.len());
                             }
 
                             children.push(child);
                         },
                         Err(e) => {
                             eprintln!("Failed to start {}: {}", next_name, e);
-                            return;
+                            return 126;
                         }
                     }
 
@@ -243,9 +309,16 @@ pub fn run_pipeline(commands: &[Command]) {
         }
     }
 
-    for mut child in children {
-        let _ = child.wait();
+    for (idx, mut child) in children.into_iter().enumerate() {
+        let exit = child.wait();
+        if last_child == Some(idx) {
+            status = exit.map(|s| s.code().unwrap_or(128)).unwrap_or(1);
+        }
+    }
+    for writer in writers {
+        let _ = writer.join();
     }
+    status
 }
 
 fn unwrap_command(mut cmd: &Command) -> (&Command, Vec<(&str, &RedirectKind)>) {
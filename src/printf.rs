@@ -0,0 +1,488 @@
+//! `printf` format strings and the backslash escapes shared with `echo -e`.
+
+/// Result of `format`: the text, and a message for every argument that wasn't a valid number.
+/// `stopped` is set when a `\c` in a `%b` argument ended the output early.
+/// The text is bytes, since `\xHH` and octal escapes write single bytes that need not be UTF-8.
+#[derive(Debug, Default, PartialEq)]
+pub struct Output {
+    pub text: Vec<u8>,
+    pub errors: Vec<String>,
+    pub stopped: bool,
+}
+
+/// Expands backslash escapes: `\\ \a \b \e \f \n \r \t \v`, `\xHH`, `\uHHHH`, `\UHHHHHHHH` and octal.
+/// In `echo` style (`echo -e`, `%b`) octal is written `\0NNN` and `\c` ends the output, which is reported
+/// with `true`; otherwise (a `printf` format) octal is `\NNN` and `\" \' \?` stand for themselves.
+/// `\xHH` and octal escapes are raw bytes, `\u` and `\U` UTF-8 encoded chars.
+pub fn unescape(s: &str, echo_style: bool) -> (Vec<u8>, bool) {
+    let mut out = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push_char(&mut out, c);
+            continue;
+        }
+        let Some(e) = chars.next() else {
+            out.push(b'\\');
+            break;
+        };
+        match e {
+            '\\' => out.push(b'\\'),
+            'a' => out.push(0x07),
+            'b' => out.push(0x08),
+            'e' | 'E' => out.push(0x1b),
+            'f' => out.push(0x0c),
+            'n' => out.push(b'\n'),
+            'r' => out.push(b'\r'),
+            't' => out.push(b'\t'),
+            'v' => out.push(0x0b),
+            'c' if echo_style => return (out, true),
+            '"' | '\'' | '?' if !echo_style => push_char(&mut out, e),
+            'x' | 'u' | 'U' => {
+                let max = match e {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let digits = take_digits(&mut chars, 16, max);
+                match u32::from_str_radix(&digits, 16).ok() {
+                    Some(n) if e == 'x' => out.push(n as u8),
+                    Some(n) => push_char(&mut out, char::from_u32(n).unwrap_or('\u{fffd}')),
+                    None => {
+                        out.push(b'\\');
+                        push_char(&mut out, e);
+                    }
+                }
+            }
+            '0'..='7' => {
+                let digits = if echo_style && e == '0' {
+                    take_digits(&mut chars, 8, 3)
+                } else if echo_style {
+                    // `echo -e '\1'` prints it as is
+                    out.push(b'\\');
+                    push_char(&mut out, e);
+                    continue;
+                } else {
+                    let mut digits = e.to_string();
+                    digits.push_str(&take_digits(&mut chars, 8, 2));
+                    digits
+                };
+                out.push(u32::from_str_radix(&digits, 8).unwrap_or(0) as u8);
+            }
+            other => {
+                out.push(b'\\');
+                push_char(&mut out, other);
+            }
+        }
+    }
+    (out, false)
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>, radix: u32, max: usize) -> String {
+    let mut digits = String::new();
+    while digits.len() < max
+        && let Some(&d) = chars.peek()
+        && d.is_digit(radix)
+    {
+        digits.push(d);
+        chars.next();
+    }
+    digits
+}
+
+/// Formats `args` with `format` like printf(1): the format is reused until all arguments are consumed,
+/// missing arguments count as empty strings or zero.
+pub fn format(format: &str, args: &[String]) -> Output {
+    let format: Vec<char> = format.chars().collect();
+    let mut out = Output::default();
+    let mut args = args.iter().map(String::as_str);
+    let mut remaining = args.len();
+    loop {
+        let mut consumed = false;
+        let mut next_arg = || {
+            consumed = true;
+            remaining = remaining.saturating_sub(1);
+            args.next()
+        };
+        let mut i = 0;
+        while i < format.len() {
+            match format[i] {
+                '\\' => {
+                    // one escape at a time, so `\c`-like sequences never eat a following `%`
+                    let end = escape_len(&format[i..]);
+                    let escape: String = format[i..i + end].iter().collect();
+                    out.text.extend(unescape(&escape, false).0);
+                    i += end;
+                }
+                '%' if format.get(i + 1) == Some(&'%') => {
+                    out.text.push(b'%');
+                    i += 2;
+                }
+                '%' => {
+                    let (spec, len) = Spec::parse(&format[i + 1..], &mut next_arg, &mut out.errors);
+                    i += 1 + len;
+                    let Some(spec) = spec else {
+                        // not a conversion: print it as it was written
+                        format[i - len - 1..i].iter().for_each(|&c| push_char(&mut out.text, c));
+                        continue;
+                    };
+                    let arg = next_arg().unwrap_or("");
+                    if spec.convert(arg, &mut out) {
+                        return out;
+                    }
+                }
+                c => {
+                    push_char(&mut out.text, c);
+                    i += 1;
+                }
+            }
+        }
+        if !consumed || remaining == 0 {
+            return out;
+        }
+    }
+}
+
+/// Length of the escape sequence at the start of `s` (which starts with a backslash).
+fn escape_len(s: &[char]) -> usize {
+    let (radix, max) = match s.get(1) {
+        None => return 1,
+        Some('x') => (16, 2),
+        Some('u') => (16, 4),
+        Some('U') => (16, 8),
+        Some('0'..='7') => return 2 + s[2..].iter().take(2).take_while(|c| c.is_digit(8)).count(),
+        Some(_) => return 2,
+    };
+    2 + s[2..].iter().take(max).take_while(|c| c.is_digit(radix)).count()
+}
+
+/// The largest width or precision accepted, so a huge one is an error rather than a huge allocation.
+const MAX_FIELD: u64 = 1 << 20;
+
+/// One `%[flags][width][.precision]conversion`.
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+impl Spec {
+    /// Parses the spec after a `%`, taking `*` widths and precisions from the arguments.
+    /// Returns the spec (`None` if the conversion is unknown) and the number of chars read.
+    fn parse<'a>(
+        s: &[char],
+        next_arg: &mut impl FnMut() -> Option<&'a str>,
+        errors: &mut Vec<String>,
+    ) -> (Option<Spec>, usize) {
+        let mut spec = Spec {
+            left: false,
+            zero: false,
+            plus: false,
+            space: false,
+            alternate: false,
+            width: 0,
+            precision: None,
+            conversion: ' ',
+        };
+        let mut i = 0;
+        while let Some(&c) = s.get(i) {
+            match c {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let mut number = |i: &mut usize, errors: &mut Vec<String>| -> i64 {
+            if s.get(*i) == Some(&'*') {
+                *i += 1;
+                return parse_int(next_arg().unwrap_or(""), errors);
+            }
+            let digits: String = s[*i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+            *i += digits.len();
+            digits.parse().unwrap_or(if digits.is_empty() { 0 } else { i64::MAX })
+        };
+        let width = number(&mut i, errors);
+        if width.unsigned_abs() > MAX_FIELD {
+            errors.push(format!("{width}: invalid field width"));
+        } else {
+            if width < 0 {
+                spec.left = true; // as in C, a negative `*` width means left-aligned
+            }
+            spec.width = width.unsigned_abs() as usize;
+        }
+        if s.get(i) == Some(&'.') {
+            i += 1;
+            // a negative `*` precision counts as none
+            let precision = number(&mut i, errors);
+            if precision > MAX_FIELD as i64 {
+                errors.push(format!("{precision}: invalid precision"));
+            } else {
+                spec.precision = usize::try_from(precision).ok();
+            }
+        }
+        // length modifiers mean nothing here
+        while s.get(i).is_some_and(|c| "hlLjzt".contains(*c)) {
+            i += 1;
+        }
+        match s.get(i) {
+            Some(&c) if "sbqcdiuxXofFeE".contains(c) => {
+                spec.conversion = c;
+                (Some(spec), i + 1)
+            }
+            Some(_) => (None, i + 1),
+            None => (None, i),
+        }
+    }
+
+    /// Appends `arg` converted per the spec to `out`. Returns true if a `\c` ended the output.
+    fn convert(&self, arg: &str, out: &mut Output) -> bool {
+        let text = match self.conversion {
+            's' => self.truncate(arg.as_bytes()),
+            'b' => {
+                let (text, stop) = unescape(arg, true);
+                if stop {
+                    out.text.extend(self.pad(self.truncate(&text)));
+                    out.stopped = true;
+                    return true;
+                }
+                self.truncate(&text)
+            }
+            'q' => quote(arg).into_bytes(),
+            'c' => arg.chars().next().map(|c| c.to_string().into_bytes()).unwrap_or_default(),
+            'd' | 'i' => {
+                let n = parse_int(arg, &mut out.errors);
+                let sign = if n < 0 { "-" } else if self.plus { "+" } else if self.space { " " } else { "" };
+                return self.push_number(out, sign, &n.unsigned_abs().to_string(), "");
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                let n = parse_int(arg, &mut out.errors) as u64; // negative numbers wrap, as in C
+                let (digits, prefix) = match self.conversion {
+                    'u' => (n.to_string(), ""),
+                    'x' => (format!("{n:x}"), "0x"),
+                    'X' => (format!("{n:X}"), "0X"),
+                    _ => (format!("{n:o}"), "0"),
+                };
+                let prefix = if self.alternate && n != 0 { prefix } else { "" };
+                return self.push_number(out, "", &digits, prefix);
+            }
+            _ => {
+                let x = parse_float(arg, &mut out.errors);
+                let precision = self.precision.unwrap_or(6);
+                let digits = match self.conversion {
+                    'e' | 'E' => exponent(x.abs(), precision, self.conversion == 'E'),
+                    _ => format!("{:.*}", precision, x.abs()),
+                };
+                let sign = if x.is_sign_negative() { "-" } else if self.plus { "+" } else if self.space { " " } else { "" };
+                let text = format!("{sign}{digits}");
+                let zero = self.zero && !self.left && x.is_finite();
+                out.text.extend(self.pad_number(&text, sign.len(), zero));
+                return false;
+            }
+        };
+        out.text.extend(self.pad(text));
+        false
+    }
+
+    /// Integers: the precision is the minimum number of digits, and turns off zero padding.
+    fn push_number(&self, out: &mut Output, sign: &str, digits: &str, prefix: &str) -> bool {
+        let digits = match self.precision {
+            Some(0) if digits == "0" => String::new(),
+            Some(p) if p > digits.len() => format!("{}{digits}", "0".repeat(p - digits.len())),
+            _ => digits.to_string(),
+        };
+        let text = format!("{sign}{prefix}{digits}");
+        let zero = self.zero && !self.left && self.precision.is_none();
+        out.text.extend(self.pad_number(&text, sign.len() + prefix.len(), zero));
+        false
+    }
+
+    /// Pads to the width; zeros go after the sign or prefix, which take `head` bytes of `text`.
+    fn pad_number(&self, text: &str, head: usize, zero: bool) -> Vec<u8> {
+        if zero && text.len() < self.width {
+            return format!("{}{}{}", &text[..head], "0".repeat(self.width - text.len()), &text[head..]).into_bytes();
+        }
+        self.pad(text.as_bytes().to_vec())
+    }
+
+    /// Widths and precisions count chars: the bytes that don't continue a UTF-8 sequence.
+    fn pad(&self, mut text: Vec<u8>) -> Vec<u8> {
+        let len = text.iter().filter(|&&b| !is_continuation(b)).count();
+        if len < self.width {
+            let padding = std::iter::repeat_n(b' ', self.width - len);
+            if self.left {
+                text.extend(padding);
+            } else {
+                text.splice(0..0, padding);
+            }
+        }
+        text
+    }
+
+    fn truncate(&self, s: &[u8]) -> Vec<u8> {
+        let end = self.precision.and_then(|p| {
+            s.iter().enumerate().filter(|&(_, &b)| !is_continuation(b)).nth(p).map(|(i, _)| i)
+        });
+        s[..end.unwrap_or(s.len())].to_vec()
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// An integer argument: decimal, `0x` hex, `0` octal, or `'c`/`"c` for the code of character `c`.
+/// Invalid numbers are reported in `errors` and count as their valid prefix (or zero).
+fn parse_int(arg: &str, errors: &mut Vec<String>) -> i64 {
+    let s = arg.trim_start();
+    if let Some(rest) = s.strip_prefix(['\'', '"']) {
+        return rest.chars().next().map_or(0, |c| c as i64);
+    }
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (radix, digits) = if let Some(hex) = unsigned.strip_prefix("0x").or_else(|| unsigned.strip_prefix("0X")) {
+        (16, hex)
+    } else if unsigned.len() > 1 && unsigned.starts_with('0') {
+        (8, &unsigned[1..])
+    } else {
+        (10, unsigned)
+    };
+    let valid = digits.chars().take_while(|c| c.is_digit(radix)).count();
+    if arg.is_empty() {
+        return 0;
+    }
+    if valid == 0 || valid < digits.len() {
+        errors.push(format!("{arg}: invalid number"));
+    }
+    let n = i64::from_str_radix(&digits[..valid], radix).unwrap_or(0);
+    if negative { -n } else { n }
+}
+
+fn parse_float(arg: &str, errors: &mut Vec<String>) -> f64 {
+    let s = arg.trim();
+    if s.is_empty() {
+        return 0.0;
+    }
+    if let Some(rest) = s.strip_prefix(['\'', '"']) {
+        return rest.chars().next().map_or(0.0, |c| c as u32 as f64);
+    }
+    match s.parse() {
+        Ok(x) => x,
+        Err(_) => {
+            errors.push(format!("{arg}: invalid number"));
+            0.0
+        }
+    }
+}
+
+/// `x` (not negative) in C's `%e` notation: `d.ddde+XX`.
+fn exponent(x: f64, precision: usize, upper: bool) -> String {
+    if !x.is_finite() {
+        return format!("{x}");
+    }
+    // Rust's `{:e}` rounds correctly but writes the exponent as `e5` / `e-5`
+    let rust = format!("{:.*e}", precision, x);
+    let (mantissa, exp) = rust.split_once('e').unwrap_or((&rust, "0"));
+    let exp: i32 = exp.parse().unwrap_or(0);
+    let e = if upper { 'E' } else { 'e' };
+    format!("{mantissa}{e}{}{:02}", if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+/// `%q`: `s` quoted so the shell reads it back as one word.
+fn quote(s: &str) -> String {
+    if s.is_empty() {
+        return "''".to_string();
+    }
+    if s.chars().any(|c| c.is_control()) {
+        let mut out = String::from("$'");
+        for c in s.chars() {
+            match c {
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                '\r' => out.push_str("\\r"),
+                '\x1b' => out.push_str("\\E"),
+                '\\' | '\'' => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                c if c.is_control() => out.push_str(&format!("\\{:03o}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('\'');
+        return out;
+    }
+    let mut out = String::new();
+    for (i, c) in s.chars().enumerate() {
+        let safe = c.is_alphanumeric() || "_./:,+@%=-".contains(c) || (c == '~' && i > 0);
+        if !safe {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printf(format_str: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        String::from_utf8(format(format_str, &args).text).unwrap()
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape(r"a\tb\n\x41\0101\c ignored", true), (b"a\tb\nAA".to_vec(), true));
+        assert_eq!(unescape(r"\101é\q\", false), ("Aé\\q\\".as_bytes().to_vec(), false));
+    }
+
+    #[test]
+    fn test_escapes_are_bytes() {
+        // `\xHH` and octal are single bytes, even when they are not valid UTF-8 alone
+        assert_eq!(unescape(r"\xff\xc3\xa9\0377", true).0, [0xff, 0xc3, 0xa9, 0xff]);
+        assert_eq!(unescape(r"\351\u00e9", false).0, [0xe9, 0xc3, 0xa9]);
+        let out = format(r"%b|\x80%s|%3.1b", &[r"\x00".to_string(), "é".to_string(), "éa".to_string()]);
+        assert_eq!(out.text, [0, b'|', 0x80, 0xc3, 0xa9, b'|', b' ', b' ', 0xc3, 0xa9]);
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(printf("%s-%5s-%-5s|%.2s", &["a", "b", "c", "xyz"]), "a-    b-c    |xy");
+        assert_eq!(printf("%d %i %+d %05d %.3d %x %X %#o %o", &["42", "-7", "3", "-42", "7", "255", "255", "8", "-1"]),
+            "42 -7 +3 -0042 007 ff FF 010 1777777777777777777777");
+        assert_eq!(printf("%d %d %d", &["0x10", "010", "'A"]), "16 8 65");
+        assert_eq!(printf("%f %.2f %8.3f %e %E %.0e", &["1.5", "2.345", "-3.14159", "1234.5", "0.00012", "5"]),
+            "1.500000 2.35   -3.142 1.234500e+03 1.200000E-04 5e+00");
+        assert_eq!(printf("%c%c %b %q %q %%", &["hello", "w", r"a\tb", "a b'c", ""]), "hw a\tb a\\ b\\'c '' %");
+        assert_eq!(printf("%*d|%-*s|%.*f", &["4", "7", "3", "x", "1", "2.25"]), "   7|x  |2.2");
+    }
+
+    #[test]
+    fn test_format_reuse_and_errors() {
+        assert_eq!(printf("%s=%d\n", &["a", "1", "b"]), "a=1\nb=0\n");
+        assert_eq!(printf("no conversions\n", &["x", "y"]), "no conversions\n");
+        let out = format("%d|%b|%s", &["12abc".to_string(), r"x\cy".to_string(), "never".to_string()]);
+        assert_eq!(out, Output { text: b"12|x".to_vec(), errors: vec!["12abc: invalid number".to_string()], stopped: true });
+        let out = format("%*d|%.*s|%99999999999999999999d|%.*f|", &["99999999999".to_string(), "1".to_string(),
+            "-99999999999".to_string(), "ab".to_string(), "2".to_string(), "99999999999".to_string(), "1".to_string()]);
+        assert_eq!(out.text, b"1|ab|2|1.000000|");
+        assert_eq!(out.errors, ["99999999999: invalid field width", "9223372036854775807: invalid field width",
+            "99999999999: invalid precision"]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::env;
+use std::path::Path;
+
+use crate::{dirs, history, state, sys};
+
+pub const DEFAULT_PS1: &str = "$ ";
+pub const DEFAULT_PS2: &str = "> ";
+
+/// An expanded prompt.
+/// `plain` is handed to rustyline (it is what the cursor math is based on),
+/// `styled` additionally keeps the `\[ ... \]` sequences and is shown via `Highlighter::highlight_prompt`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Prompt {
+    pub plain: String,
+    pub styled: String,
+}
+
+impl Prompt {
+    pub fn prepend(&mut self, s: &str) {
+        self.plain.insert_str(0, s);
+        self.styled.insert_str(0, s);
+    }
+}
+
+/// `$PS1` (or `$PS2` when `continuation`) with bash-style backslash escapes expanded.
+pub fn current(continuation: bool) -> Prompt {
+    let (var, default) = if continuation { ("PS2", DEFAULT_PS2) } else { ("PS1", DEFAULT_PS1) };
+    expand_or(env::var(var).ok().as_deref(), default)
+}
+
+/// `ps` expanded, or `default` as it is when the variable is unset.
+fn expand_or(ps: Option<&str>, default: &str) -> Prompt {
+    match ps {
+        Some(ps) => expand(ps),
+        None => Prompt { plain: default.to_string(), styled: default.to_string() },
+    }
+}
+
+/// Expands the escapes bash supports in prompt strings:
+/// `\u \h \H \w \W \$ \t \T \@ \A \d \j \? \# \! \s \v \V \n \r \a \e \\ \nnn`,
+/// plus `\[ ... \]` around non-printing sequences (ANSI colours).
+/// `\j` is always 0: the shell runs every command in the foreground, so it never has jobs.
+pub fn expand(ps: &str) -> Prompt {
+    let mut prompt = Prompt::default();
+    let mut non_printing = false;
+    let mut chars = ps.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push(&mut prompt, non_printing, &c.to_string());
+            continue;
+        }
+        let Some(e) = chars.next() else {
+            push(&mut prompt, non_printing, "\\");
+            break;
+        };
+        let s = match e {
+            '[' => { non_printing = true; continue; }
+            ']' => { non_printing = false; continue; }
+            'u' => sys::username(),
+            'h' => sys::hostname().split('.').next().unwrap_or_default().to_string(),
+            'H' => sys::hostname(),
+            'w' => abbreviate_home(&cwd()),
+            'W' => {
+                let cwd = cwd();
+                if env::var("HOME").is_ok_and(|home| home == cwd) {
+                    "~".to_string()
+                } else if cwd == "/" {
+                    cwd
+                } else {
+                    Path::new(&cwd).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(cwd)
+                }
+            }
+            '$' => if sys::is_root() { "#" } else { "$" }.to_string(),
+            't' => sys::strftime("%H:%M:%S", sys::now()),
+            'T' => sys::strftime("%I:%M:%S", sys::now()),
+            '@' => sys::strftime("%I:%M %p", sys::now()),
+            'A' => sys::strftime("%H:%M", sys::now()),
+            'd' => sys::strftime("%a %b %d", sys::now()),
+            'j' => "0".to_string(),
+            '?' => state::last_status().to_string(),
+            '#' => (state::command_count() + 1).to_string(),
+            '!' => (history::len() + 1).to_string(),
+            's' => "craft-shell".to_string(),
+            'v' => env!("CARGO_PKG_VERSION").rsplit_once('.').map_or("", |(v, _)| v).to_string(),
+            'V' => env!("CARGO_PKG_VERSION").to_string(),
+            'n' => "\n".to_string(),
+            'r' => "\r".to_string(),
+            'a' => "\x07".to_string(),
+            'e' => "\x1b".to_string(),
+            '\\' => "\\".to_string(),
+            '0'..='7' => {
+                let mut code = e.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|d| d.to_digit(8)) {
+                        Some(d) => { code = code * 8 + d; chars.next(); }
+                        None => break,
+                    }
+                }
+                char::from_u32(code).map(String::from).unwrap_or_default()
+            }
+            other => format!("\\{other}"),
+        };
+        push(&mut prompt, non_printing, &s);
+    }
+    prompt
+}
+
+fn push(prompt: &mut Prompt, non_printing: bool, s: &str) {
+    if !non_printing {
+        prompt.plain.push_str(s);
+    }
+    prompt.styled.push_str(s);
+}
+
+fn cwd() -> String {
+    dirs::cwd().display().to_string()
+}
+
+/// Replaces a leading `$HOME` with `~`.
+pub fn abbreviate_home(path: &str) -> String {
+    if let Ok(home) = env::var("HOME")
+        && !home.is_empty()
+        && let Some(rest) = path.strip_prefix(&home)
+        && (rest.is_empty() || rest.starts_with('/'))
+    {
+        return format!("~{rest}");
+    }
+    path.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_prompt() {
+        let p = expand("$ ");
+        assert_eq!(p.plain, "$ ");
+        assert_eq!(p.styled, "$ ");
+    }
+
+    #[test]
+    fn test_non_printing_sequences() {
+        let p = expand("\\[\\e[32m\\]ok\\[\\e[0m\\] ");
+        assert_eq!(p.plain, "ok ");
+        assert_eq!(p.styled, "\x1b[32mok\x1b[0m ");
+    }
+
+    #[test]
+    fn test_octal_and_backslash() {
+        let p = expand("\\101\\\\\\n");
+        assert_eq!(p.plain, "A\\\n");
+    }
+
+    #[test]
+    fn test_unknown_escape_is_kept() {
+        assert_eq!(expand("\\q").plain, "\\q");
+    }
+
+    #[test]
+    fn test_continuation_prompt() {
+        assert_eq!(expand_or(None, DEFAULT_PS2).plain, DEFAULT_PS2);
+        assert_eq!(expand_or(Some("\\s> "), DEFAULT_PS2).plain, "craft-shell> ");
+    }
+}
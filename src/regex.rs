@@ -0,0 +1,488 @@
+//! A small POSIX extended regular expression engine, for `[[ string =~ regex ]]`.
+//!
+//! Supports literals, `.`, bracket expressions (ranges, `^` negation, `[:class:]`), the `^` and `$` anchors,
+//! groups, alternation and the `*`, `+`, `?` and `{n,m}` repetitions; `\` makes the next character literal.
+//! The pattern is compiled to a small program that a Pike VM runs on all paths through it at once, so matching
+//! takes time linear in the text and no recursion, whatever the pattern. It returns the match at the leftmost
+//! position that a backtracker would find first, trying repetitions greedily and alternatives in order.
+//! That is POSIX's leftmost-longest match except for some alternations.
+
+/// Patterns nesting groups or repetitions deeper than this are refused, as the parser and compiler recurse.
+const MAX_NESTING: usize = 200;
+/// Compiled programs are limited to this many instructions: `{n,m}` copies what it repeats.
+const MAX_PROGRAM: usize = 10_000;
+
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    /// A parenthesized group and its number, counting opening parentheses from 1.
+    Group(Box<Node>, usize),
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    /// The node repeated at least `min` and at most `max` times.
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(char, char),
+    Named(fn(char) -> bool),
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let found = self.items.iter().any(|item| match item {
+            ClassItem::Range(lo, hi) => (*lo..=*hi).contains(&c),
+            ClassItem::Named(is) => is(c),
+        });
+        found != self.negated
+    }
+}
+
+/// A Pike VM instruction. Group `n` records where it starts and ends in slots `2n` and `2n + 1`,
+/// group 0 being the whole match.
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Save(usize),
+    /// Continues at both, preferring the first.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize,
+}
+
+impl Regex {
+    /// Compiles `pattern`; the error describes what is wrong with it.
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, groups: 0, depth: 0 };
+        let root = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err("unmatched )".to_string()); // `alternation` only stops early at a `)`
+        }
+        let mut program = vec![Inst::Save(0)];
+        compile(root, &mut program)?;
+        program.extend([Inst::Save(1), Inst::Match]);
+        Ok(Regex { program, groups: parser.groups })
+    }
+
+    /// The byte ranges of the match in `text` (element 0) and of each group in it (`None` for a group
+    /// that took no part in the match), or `None` if `text` doesn't match.
+    pub fn captures(&self, text: &str) -> Option<Vec<Option<(usize, usize)>>> {
+        let chars: Vec<char> = text.chars().collect();
+        let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+        let mut vm = Vm { program: &self.program, len: chars.len(), added: vec![usize::MAX; self.program.len()] };
+        // the threads at the current position, highest priority first
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        let mut found = None;
+        for pos in 0..=chars.len() {
+            if found.is_none() {
+                // a match starting here ranks below every one that started further left
+                vm.add(&mut current, 0, pos, vec![None; 2 * (self.groups + 1)]);
+            }
+            if current.is_empty() && found.is_some() {
+                break;
+            }
+            let c = chars.get(pos).copied();
+            for (pc, slots) in current.drain(..) {
+                let step = match &self.program[pc] {
+                    Inst::Char(want) => c == Some(*want),
+                    Inst::Any => c.is_some(),
+                    Inst::Class(class) => c.is_some_and(|c| class.matches(c)),
+                    Inst::Match => {
+                        // the threads after this one would only give lower-priority matches
+                        found = Some(slots);
+                        break;
+                    }
+                    _ => unreachable!("`add` follows every other instruction"),
+                };
+                if step {
+                    vm.add(&mut next, pc + 1, pos + 1, slots);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        let slots = found?;
+        Some(slots.chunks(2).map(|g| Some((offsets[g[0]?], offsets[g[1]?]))).collect())
+    }
+}
+
+/// A thread: the instruction it is at, and the group slots it recorded.
+type Thread = (usize, Vec<Option<usize>>);
+
+struct Vm<'a> {
+    program: &'a [Inst],
+    len: usize,
+    /// For each instruction, the last position where a thread reached it; a later thread there is redundant.
+    added: Vec<usize>,
+}
+
+impl Vm<'_> {
+    /// Adds to `list` the threads that reach a char-consuming instruction or `Match` from `pc` at `pos`,
+    /// in priority order. An explicit stack instead of recursion, and each instruction is entered at most
+    /// once per position, which also ends loops around repetitions that match the empty string.
+    fn add(&mut self, list: &mut Vec<Thread>, pc: usize, pos: usize, slots: Vec<Option<usize>>) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((pc, mut slots)) = stack.pop() {
+            if self.added[pc] == pos {
+                continue;
+            }
+            self.added[pc] = pos;
+            match self.program[pc] {
+                Inst::Jump(to) => stack.push((to, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((second, slots.clone()));
+                    stack.push((first, slots));
+                }
+                Inst::Save(slot) => {
+                    slots[slot] = Some(pos);
+                    stack.push((pc + 1, slots));
+                }
+                Inst::Start if pos == 0 => stack.push((pc + 1, slots)),
+                Inst::End if pos == self.len => stack.push((pc + 1, slots)),
+                Inst::Start | Inst::End => {}
+                _ => list.push((pc, slots)),
+            }
+        }
+    }
+}
+
+/// Appends the instructions for `node` to `program`.
+fn compile(node: Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM {
+        return Err("regular expression too big".to_string());
+    }
+    match node {
+        Node::Char(c) => program.push(Inst::Char(c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(class) => program.push(Inst::Class(class)),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(inner, number) => {
+            program.push(Inst::Save(2 * number));
+            compile(*inner, program)?;
+            program.push(Inst::Save(2 * number + 1));
+        }
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alternation(mut alternatives) => {
+            let last = alternatives.pop().unwrap();
+            let mut jumps = Vec::new();
+            for alternative in alternatives {
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(alternative, program)?;
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            compile(last, program)?;
+            for jump in jumps {
+                program[jump] = Inst::Jump(program.len());
+            }
+        }
+        Node::Repeat(inner, min, max) => {
+            // `x{2,4}` is `xx` then two optional copies, each tried only if the one before matched;
+            // `x{2,}` is `xx` then a loop
+            let inner = *inner;
+            for _ in 0..min {
+                compile(inner.clone(), program)?;
+            }
+            let mut splits = Vec::new();
+            match max {
+                Some(max) => {
+                    for _ in min..max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile(inner.clone(), program)?;
+                    }
+                }
+                None => {
+                    let split = program.len();
+                    splits.push(split);
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(inner, program)?;
+                    program.push(Inst::Jump(split));
+                }
+            }
+            for split in splits {
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    /// How many groups and repetitions enclose the current position.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.concat()?);
+        }
+        Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Node::Alternation(alternatives) })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek()
+            && c != '|'
+            && c != ')'
+        {
+            let atom = self.atom()?;
+            nodes.push(self.repetitions(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.chars[self.pos];
+        self.pos += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                self.nest(1)?;
+                self.groups += 1;
+                let number = self.groups;
+                let inner = self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err("unmatched (".to_string());
+                }
+                self.pos += 1;
+                self.depth -= 1;
+                Node::Group(Box::new(inner), number)
+            }
+            '[' => Node::Class(self.class()?),
+            '\\' => match self.peek() {
+                Some(next) => {
+                    self.pos += 1;
+                    Node::Char(next)
+                }
+                None => return Err("trailing backslash".to_string()),
+            },
+            '*' | '+' | '?' => return Err(format!("nothing to repeat before {c}")),
+            _ => Node::Char(c),
+        })
+    }
+
+    fn nest(&mut self, levels: usize) -> Result<(), String> {
+        self.depth += levels;
+        if self.depth > MAX_NESTING {
+            return Err("regular expression nested too deeply".to_string());
+        }
+        Ok(())
+    }
+
+    /// Wraps `node` in the repetitions that follow it.
+    fn repetitions(&mut self, mut node: Node) -> Result<Node, String> {
+        let mut count = 0;
+        loop {
+            let (min, max) = match self.peek() {
+                Some('{') => match self.interval()? {
+                    Some(bounds) => bounds,
+                    None => break, // not an interval: a literal `{`
+                },
+                Some(c @ ('*' | '+' | '?')) => {
+                    self.pos += 1;
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => break,
+            };
+            node = Node::Repeat(Box::new(node), min, max);
+            count += 1;
+            self.nest(1)?;
+        }
+        self.depth -= count;
+        Ok(node)
+    }
+
+    /// `{n}`, `{n,}` or `{n,m}`; moves past it only if it is one.
+    fn interval(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let Some(end) = rest.find('}') else {
+            return Ok(None);
+        };
+        let body = &rest[..end];
+        let (min, max) = match body.split_once(',') {
+            None => (body, Some(body)),
+            Some((min, "")) => (min, None),
+            Some((min, max)) => (min, Some(max)),
+        };
+        let (Ok(min), Ok(max)) = (min.parse::<usize>(), max.map(str::parse::<usize>).transpose()) else {
+            return Ok(None);
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err("invalid repetition count".to_string());
+        }
+        self.pos += 1 + body.chars().count() + 1;
+        Ok(Some((min, max)))
+    }
+
+    /// A bracket expression, after its `[`. A `]` right after `[` or `[^` is a literal.
+    fn class(&mut self) -> Result<Class, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let Some(c) = self.peek() else {
+                return Err("unmatched [".to_string());
+            };
+            self.pos += 1;
+            if c == ']' && !first {
+                return Ok(Class { negated, items });
+            }
+            first = false;
+            if c == '[' && self.peek() == Some(':') {
+                let rest: String = self.chars[self.pos + 1..].iter().collect();
+                let Some(end) = rest.find(":]") else {
+                    return Err("unmatched [".to_string());
+                };
+                let name = &rest[..end];
+                let is = named_class(name).ok_or_else(|| format!("invalid character class {name}"))?;
+                items.push(ClassItem::Named(is));
+                self.pos += 1 + name.chars().count() + 2;
+            } else if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                let hi = self.chars[self.pos + 1];
+                self.pos += 2;
+                if hi < c {
+                    return Err("invalid range end".to_string());
+                }
+                items.push(ClassItem::Range(c, hi));
+            } else {
+                items.push(ClassItem::Range(c, c));
+            }
+        }
+    }
+}
+
+fn named_class(name: &str) -> Option<fn(char) -> bool> {
+    Some(match name {
+        "alpha" => char::is_alphabetic,
+        "digit" => |c| c.is_ascii_digit(),
+        "alnum" => char::is_alphanumeric,
+        "upper" => char::is_uppercase,
+        "lower" => char::is_lowercase,
+        "space" => char::is_whitespace,
+        "blank" => |c| c == ' ' || c == '\t',
+        "punct" => |c| c.is_ascii_punctuation(),
+        "xdigit" => |c| c.is_ascii_hexdigit(),
+        "cntrl" => char::is_control,
+        "print" => |c| !c.is_control(),
+        "graph" => |c| !c.is_control() && !c.is_whitespace(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(pattern: &str, text: &str) -> Option<Vec<Option<String>>> {
+        let regex = Regex::new(pattern).unwrap();
+        let captures = regex.captures(text)?;
+        Some(captures.into_iter().map(|g| g.map(|(s, e)| text[s..e].to_string())).collect())
+    }
+
+    fn some(groups: &[&str]) -> Option<Vec<Option<String>>> {
+        Some(groups.iter().map(|g| Some(g.to_string())).collect())
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(groups("b+", "abbbc"), some(&["bbb"]));
+        assert_eq!(groups("^a.c$", "abc"), some(&["abc"]));
+        assert_eq!(groups("^a.c$", "xabc"), None);
+        assert_eq!(groups("colou?r", "my color"), some(&["color"]));
+        assert_eq!(groups("[[:digit:]]{2,3}", "a12345"), some(&["123"]));
+        assert_eq!(groups("[^a-c]+", "abcdef"), some(&["def"]));
+        assert_eq!(groups("x{2}", "x{2}xx"), some(&["xx"]));
+        assert_eq!(groups("a{", "a{"), some(&["a{"]));
+        assert_eq!(groups("\\.txt$", "atxt.txt"), some(&[".txt"]));
+        assert_eq!(groups("(a*)*b", "aaab"), some(&["aaab", "aaa"]));
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(groups("([a-z]+)-([0-9]+)", "id: abc-42!"), some(&["abc-42", "abc", "42"]));
+        assert_eq!(groups("^(cat|dog)s?$", "dogs"), some(&["dogs", "dog"]));
+        assert_eq!(groups("(x)|(y)", "y"), Some(vec![Some("y".to_string()), None, Some("y".to_string())]));
+        assert_eq!(groups("(.)+", "abc"), some(&["abc", "c"]));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Regex::new("(a").is_err());
+        assert!(Regex::new("a)").is_err());
+        assert!(Regex::new("[a").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("a{3,1}").is_err());
+        assert!(Regex::new("[[:foo:]]").is_err());
+        assert!(Regex::new("(a{100}){200}").is_err());
+        let deep = format!("{}a{}", "(".repeat(5000), ")".repeat(5000));
+        assert!(Regex::new(&deep).is_err());
+        assert!(Regex::new(&format!("a{}", "*".repeat(5000))).is_err());
+    }
+
+    #[test]
+    fn test_long_input() {
+        let text = "a".repeat(20000);
+        assert_eq!(Regex::new("^a*$").unwrap().captures(&text), Some(vec![Some((0, 20000))]));
+        assert_eq!(Regex::new("^(a|b)*c").unwrap().captures(&text), None);
+        assert_eq!(Regex::new("(a)+$").unwrap().captures(&text), Some(vec![Some((0, 20000)), Some((19999, 20000))]));
+    }
+
+    #[test]
+    fn test_nested_quantifiers() {
+        // exponential for a backtracker
+        let text = "a".repeat(28);
+        assert_eq!(Regex::new("(a*)*b").unwrap().captures(&text), None);
+        assert_eq!(Regex::new("^(a|aa)+$").unwrap().captures(&text).map(|g| g[0]), Some(Some((0, 28))));
+        assert_eq!(Regex::new("(x+x+)+y").unwrap().captures(&"x".repeat(5000)), None);
+        assert_eq!(groups("((a*)*)*b", "aab"), some(&["aab", "aa", "aa"]));
+        assert_eq!(groups("(a|ab)(c|bcd)(d*)", "abcd"), some(&["abcd", "a", "bcd", ""]));
+    }
+}
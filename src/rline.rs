@@ -1,53 +1,181 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
 use rustyline::completion::{Completer, Pair};
 use rustyline::error::ReadlineError;
 use rustyline::hint::Hinter;
-use rustyline::highlight::Highlighter;
+use rustyline::highlight::{CmdKind, Highlighter};
 use rustyline::validate::Validator;
-use rustyline::{Context, Helper};
+use rustyline::{
+    Cmd, ConditionalEventHandler, Context, EditMode, Event, EventContext, Helper, InputMode,
+    KeyCode, KeyEvent, Modifiers, Movement, RepeatCount,
+};
+
+use crate::builtins;
+use crate::completion::{self, PathKind};
+use crate::dirs;
+use crate::fuzzy;
+use crate::highlight;
+use crate::history;
+use crate::jump;
+use crate::prompt::Prompt;
+use crate::state;
+
+/// Prompt prefixes showing the vi input mode; both have the same width,
+/// so swapping one for the other in `highlight_prompt` keeps the cursor math right.
+pub const VI_INSERT_INDICATOR: &str = "(ins) ";
+pub const VI_COMMAND_INDICATOR: &str = "(cmd) ";
 
 pub struct ShellHelper {
     pub builtins: Vec<&'static str>,
-    pub system_commands: Vec<String>,
+    /// Executable names in `PATH` for completion and highlighting, shared with the cache in `executables`.
+    /// Refreshed before each `PS1` prompt (commands that change `PATH` or run `hash -r` run between prompts),
+    /// not on every keystroke or continuation line.
+    pub executables: Arc<Vec<String>>,
+    /// The prompt of the current `readline` call, see `prompt::Prompt`.
+    pub prompt: Prompt,
 }
 
 impl Completer for ShellHelper {
     type Candidate = Pair;
 
-    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) 
-        -> Result<(usize, Vec<Pair>), ReadlineError> 
+    /// Command names in command position; in argument position the `complete` spec
+    /// registered for the command, recorded directories for `j`, or file and directory names.
+    /// The replacement covers the whole word, opening quote included, and is re-quoted.
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>)
+        -> Result<(usize, Vec<Pair>), ReadlineError>
     {
-        let sub = &line[..pos];
-        let start = sub.rfind(' ').map_or(0, |i| i + 1);
-        let word = &sub[start..];
-
-        let builtin_matches = self.builtins.iter()
-            .filter(|c| c.starts_with(word))
-            .map(|&c| c.to_string());
+        let current = completion::current_word(line, pos);
+        let word = current.word.as_str();
 
-        let system_matches = self.system_commands.iter()
-            .filter(|c| c.starts_with(word))
-            .cloned();
+        let spec = current.command().and_then(completion::spec_for);
+        let mut candidates = if current.is_command_position() {
+            if word.contains('/') {
+                completion::complete_path(word, PathKind::Executables)
+            } else {
+                completion::complete_command(word, &self.builtins, &self.executables)
+            }
+        } else if let Some(spec) = spec {
+            spec.generate(&current, line, pos)
+        } else if current.command() == Some(builtins::CMD_J) {
+            jump::complete(word)
+        } else if current.command() == Some(builtins::CMD_CD) {
+            completion::complete_path(word, PathKind::Dirs)
+        } else {
+            completion::complete_path(word, PathKind::Any)
+        };
 
-        let candidates: Vec<Pair> = builtin_matches
-            .chain(system_matches)
-            .map(|cmd| Pair {
-                display: cmd.clone(),
-                replacement: format!("{} ", cmd),
-            })
-            .collect();
-
-        Ok((start, candidates))
+        for candidate in candidates.iter_mut() {
+            candidate.replacement = completion::quote_replacement(&candidate.replacement, current.open_quote);
+        }
+        Ok((current.start, candidates))
     }
 }
 
-// empty Traits, required by rustyline
 impl Hinter for ShellHelper {
     type Hint = String;
-    fn hint(&self, _line: &str, _pos: usize, _ctx: &Context<'_>) -> Option<String> {
-        None // No "ghost text" hints
+
+    /// Fish-style "ghost text": the rest of the latest matching history entry,
+    /// accepted with Right/End (whole) or Alt-F (word by word), see `HintAcceptor`.
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        let cwd = Some(dirs::cwd());
+        history::suggest(line, cwd.as_deref())
     }
 }
 
-impl Highlighter for ShellHelper {} // No syntax highlighting
-impl Validator for ShellHelper {}   // No input validation (Enter always submits)
+impl Highlighter for ShellHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight::highlight(line, &self.executables))
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        let styled = if prompt == self.prompt.plain { self.prompt.styled.as_str() } else { prompt };
+        if state::vi_command() && styled.starts_with(VI_INSERT_INDICATOR) {
+            Cow::Owned(styled.replacen(VI_INSERT_INDICATOR, VI_COMMAND_INDICATOR, 1))
+        } else {
+            Cow::Borrowed(styled)
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[90m{hint}\x1b[0m")) // grey
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        let vi_command_changed = state::take_vi_command_changed(); // repaint the prompt when the vi mode flips
+        vi_command_changed || kind != CmdKind::MoveCursor // re-highlight on every edit
+    }
+}
+/// Every line is accepted as typed: the main loop reads the rest of an incomplete command on `PS2` lines.
+impl Validator for ShellHelper {}
 impl Helper for ShellHelper {}      // Ties everything together
+
+/// Bound to `Event::Any`: watches keys in vi mode to track insert vs. command mode
+/// for the prompt indicator. Always returns `None`, so rustyline's own handling applies.
+pub struct ViModeTracker;
+
+impl ConditionalEventHandler for ViModeTracker {
+    fn handle(&self, evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        if ctx.mode() != EditMode::Vi {
+            return None;
+        }
+        let KeyEvent(code, mods) = *evt.get(0)?;
+        match ctx.input_mode() {
+            InputMode::Command => {
+                if mods == Modifiers::NONE
+                    && matches!(code, KeyCode::Char('i' | 'I' | 'a' | 'A' | 's' | 'S' | 'c' | 'C' | 'o' | 'O' | 'R'))
+                {
+                    state::set_vi_command(false);
+                }
+            }
+            _ => {
+                if code == KeyCode::Esc || mods.contains(Modifiers::ALT) {
+                    state::set_vi_command(true);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Bound to End and Alt-F: with the cursor at the end of the line and a history hint shown,
+/// End accepts the whole hint and Alt-F its next word; otherwise the keys keep their usual meaning.
+pub struct HintAcceptor;
+
+impl ConditionalEventHandler for HintAcceptor {
+    fn handle(&self, evt: &Event, n: RepeatCount, positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        let hint = match ctx.hint_text() {
+            Some(hint) if ctx.pos() == ctx.line().len() => hint,
+            _ => return ViModeTracker.handle(evt, n, positive, ctx), // Alt-F also leaves vi insert mode
+        };
+        match evt.get(0)? {
+            KeyEvent(KeyCode::End, _) => Some(Cmd::CompleteHint),
+            _ => {
+                let word_start = hint.len() - hint.trim_start().len();
+                let word_end = hint[word_start..]
+                    .find(char::is_whitespace)
+                    .map_or(hint.len(), |i| word_start + i);
+                Some(Cmd::Insert(1, hint[..word_end].to_string()))
+            }
+        }
+    }
+}
+
+/// Bound to Ctrl-R: the fuzzy history finder (see `fuzzy`), seeded with the current line.
+/// In vi command mode Ctrl-R keeps its meaning (redo).
+pub struct FuzzyHistorySearch;
+
+impl ConditionalEventHandler for FuzzyHistorySearch {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        if ctx.mode() == EditMode::Vi && ctx.input_mode() == InputMode::Command {
+            return None;
+        }
+        match fuzzy::run(ctx.line()) {
+            Some(command) => Some(Cmd::Replace(Movement::WholeBuffer, Some(command))),
+            None => Some(Cmd::Repaint),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Shell-wide state shared between the main loop, builtins and the line editor.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+static VI_MODE: AtomicBool = AtomicBool::new(false);
+static VI_COMMAND: AtomicBool = AtomicBool::new(false);
+static VI_COMMAND_CHANGED: AtomicBool = AtomicBool::new(false);
+static SHARE_HISTORY: AtomicBool = AtomicBool::new(false);
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+static COMMAND_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Shell variables that are not exported (`read`, `printf -v`); exported ones are environment variables.
+static VARS: LazyLock<Mutex<HashMap<String, OsString>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+/// Array variables (`read -a`, `BASH_REMATCH`), never exported.
+static ARRAYS: LazyLock<Mutex<HashMap<String, Vec<String>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `set -o vi` (true) or `set -o emacs` (false).
+pub fn set_vi_mode(on: bool) {
+    VI_MODE.store(on, Ordering::Relaxed);
+}
+
+pub fn vi_mode() -> bool {
+    VI_MODE.load(Ordering::Relaxed)
+}
+
+/// Records whether the vi editor is in command (normal) mode rather than insert mode.
+pub fn set_vi_command(on: bool) {
+    if VI_COMMAND.swap(on, Ordering::Relaxed) != on {
+        VI_COMMAND_CHANGED.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn vi_command() -> bool {
+    VI_COMMAND.load(Ordering::Relaxed)
+}
+
+/// Returns true once after each insert/command switch, so the prompt gets repainted.
+pub fn take_vi_command_changed() -> bool {
+    VI_COMMAND_CHANGED.swap(false, Ordering::Relaxed)
+}
+
+/// `set -o share_history`: sessions sharing `$HISTFILE` see each other's commands at every prompt.
+pub fn set_share_history(on: bool) {
+    SHARE_HISTORY.store(on, Ordering::Relaxed);
+}
+
+pub fn share_history() -> bool {
+    SHARE_HISTORY.load(Ordering::Relaxed)
+}
+
+/// Exit status of the most recently executed command line (`$?`).
+pub fn set_last_status(status: i32) {
+    LAST_STATUS.store(status, Ordering::Relaxed);
+}
+
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+/// Counts a command line entered at the prompt (`\#` in `PS1`).
+pub fn count_command() {
+    COMMAND_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of command lines entered in this session.
+pub fn command_count() -> usize {
+    COMMAND_COUNT.load(Ordering::Relaxed)
+}
+
+/// Checks that `value` can be stored in variable `name`. Exported variables live in the environment,
+/// which cannot hold a NUL byte, so such a value is refused with an error where `env::set_var` would panic;
+/// so is a name no variable can have. Local variables follow the same rules, so exporting one never fails.
+fn check_var(name: &str, value: &OsStr) -> io::Result<()> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a valid identifier"));
+    }
+    if value.as_bytes().contains(&0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "value contains a null byte"));
+    }
+    Ok(())
+}
+
+/// Sets variable `name` to `value` (`read`, `printf -v`). As in bash this does not export it: the value
+/// stays in the shell unless `name` is already exported, in which case the environment gets the new value.
+/// Callers either drop the NULs first (`read`) or report the error (`printf -v`).
+pub fn set_var(name: &str, value: impl AsRef<OsStr>) -> io::Result<()> {
+    let value = value.as_ref();
+    check_var(name, value)?;
+    if env::var_os(name).is_some() {
+        unsafe { env::set_var(name, value); }
+    } else {
+        VARS.lock().unwrap().insert(name.to_string(), value.to_os_string());
+    }
+    Ok(())
+}
+
+/// `export name[=value]`: puts the variable into the environment, so child processes see it.
+/// Without a value, a shell variable set earlier is exported with its current value.
+pub fn export_var(name: &str, value: Option<&OsStr>) -> io::Result<()> {
+    let local = VARS.lock().unwrap().remove(name);
+    let Some(value) = value.map(OsStr::to_os_string).or(local) else {
+        return Ok(());
+    };
+    check_var(name, &value)?;
+    unsafe { env::set_var(name, value); }
+    Ok(())
+}
+
+/// The value of variable `name`, whether it is a shell variable or exported.
+pub fn var(name: &str) -> Option<OsString> {
+    VARS.lock().unwrap().get(name).cloned().or_else(|| env::var_os(name))
+}
+
+/// Runs `f` with shell variables `vars` set (`COMP_WORDS`, ... for a builtin `complete -F` completer),
+/// then puts back whatever shell variables of those names held before. The environment is left alone.
+pub fn with_vars<T>(vars: &[(&str, String)], f: impl FnOnce() -> T) -> T {
+    let saved: Vec<(&str, Option<OsString>)> = {
+        let mut locals = VARS.lock().unwrap();
+        vars.iter().map(|(name, value)| (*name, locals.insert(name.to_string(), value.into()))).collect()
+    };
+    let result = f();
+    let mut locals = VARS.lock().unwrap();
+    for (name, value) in saved {
+        match value {
+            Some(value) => locals.insert(name.to_string(), value),
+            None => locals.remove(name),
+        };
+    }
+    result
+}
+
+/// Sets array variable `name` (`read -a`, `BASH_REMATCH`). Arrays stay in the shell: as in bash,
+/// they are never exported. Fails like `set_var` if an element contains a NUL byte.
+pub fn set_array(name: &str, values: Vec<String>) -> io::Result<()> {
+    values.iter().try_for_each(|value| check_var(name, value.as_ref()))?;
+    ARRAYS.lock().unwrap().insert(name.to_string(), values);
+    Ok(())
+}
+
+pub fn array(name: &str) -> Option<Vec<String>> {
+    ARRAYS.lock().unwrap().get(name).cloned()
+}
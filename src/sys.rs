@@ -0,0 +1,167 @@
+//! Thin safe wrappers around the few libc calls the shell needs.
+
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Formats `epoch` in local time with a strftime(3) format string.
+pub fn strftime(fmt: &str, epoch: i64) -> String {
+    let Ok(c_fmt) = CString::new(fmt) else {
+        return String::new();
+    };
+    let t = epoch as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return String::new();
+    }
+    let mut buf = vec![0u8; 256];
+    let n = unsafe { libc::strftime(buf.as_mut_ptr().cast(), buf.len(), c_fmt.as_ptr(), &tm) };
+    buf.truncate(n);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return String::new();
+    }
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Login name of the effective user, falling back to `$USER`.
+pub fn username() -> String {
+    let pw = unsafe { libc::getpwuid(libc::geteuid()) };
+    if !pw.is_null() {
+        let name = unsafe { CStr::from_ptr((*pw).pw_name) };
+        return name.to_string_lossy().into_owned();
+    }
+    env::var("USER").unwrap_or_default()
+}
+
+/// Blocks until this process holds an exclusive `flock(2)` lock on `file`.
+/// The lock is released when the file is closed.
+pub fn flock_exclusive(file: &File) -> io::Result<()> {
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Columns and rows of the terminal on stdout.
+pub fn terminal_size() -> Option<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+        return None;
+    }
+    Some((size.ws_col as usize, size.ws_row as usize))
+}
+
+/// Waits at most `timeout_ms` (-1: forever) until `fd` has input to read (or is at end of input).
+pub fn wait_readable(fd: RawFd, timeout_ms: i32) -> bool {
+    let mut fds = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    unsafe { libc::poll(&mut fds, 1, timeout_ms) > 0 }
+}
+
+/// Runs `f` with `O_NONBLOCK` set on `fd`, so a read that would wait fails with `WouldBlock` instead.
+pub fn with_nonblocking<T>(fd: RawFd, f: impl FnOnce() -> T) -> T {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || flags & libc::O_NONBLOCK != 0 {
+        return f();
+    }
+    unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+    let result = f();
+    unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+    result
+}
+
+/// Reads one byte from stdin, waiting at most `timeout_ms` (-1: forever).
+/// `None` on timeout, end of input or error.
+pub fn read_byte(timeout_ms: i32) -> Option<u8> {
+    if !wait_readable(libc::STDIN_FILENO, timeout_ms) {
+        return None;
+    }
+    let mut byte = 0u8;
+    let n = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+    (n == 1).then_some(byte)
+}
+
+/// Reads from `fd` directly, without any buffer in between.
+pub fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+    if n < 0 { Err(io::Error::last_os_error()) } else { Ok(n as usize) }
+}
+
+/// Settings of the terminal on stdin, put back when dropped.
+pub struct TerminalMode(libc::termios);
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+/// Sets up the terminal on stdin for `read`: `!echo` hides typed characters (`read -s`), and `by_char`
+/// turns off canonical mode so each byte arrives as it is typed instead of after Enter (`read -n`/`-N`).
+/// Returns the previous settings, restored when dropped; `None` if stdin is not a terminal or nothing changes.
+/// Only for input that is read from the terminal itself.
+pub fn read_mode(echo: bool, by_char: bool) -> Option<TerminalMode> {
+    if echo && !by_char {
+        return None;
+    }
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+        return None;
+    }
+    let saved = TerminalMode(termios);
+    if !echo {
+        termios.c_lflag &= !libc::ECHO;
+    }
+    if by_char {
+        termios.c_lflag &= !libc::ICANON;
+        termios.c_cc[libc::VMIN] = 1; // a read returns as soon as one byte is there
+        termios.c_cc[libc::VTIME] = 0; // with no timer: `read -t` polls instead
+    }
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+    Some(saved)
+}
+
+/// Whether the real user may access `path` with `mode` (`libc::R_OK`, `W_OK` or `X_OK`), per access(2).
+pub fn access(path: &Path, mode: libc::c_int) -> bool {
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(c_path.as_ptr(), mode) == 0 }
+}
+
+/// Whether file descriptor `fd` is open on a terminal.
+pub fn is_terminal(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Effective user and group ids.
+pub fn effective_ids() -> (u32, u32) {
+    unsafe { (libc::geteuid(), libc::getegid()) }
+}